@@ -3,6 +3,7 @@ use tokio::time::sleep;
 
 use crate::db::DbMap;
 use crate::logger::log_info;
+use crate::wal::LogRecord;
 
 /// Starts a background async task that periodically scans all databases
 /// in `db_map` and removes expired keys every 5 seconds.
@@ -29,6 +30,7 @@ pub async fn start_cleaner(db_map: DbMap) {
                     // Remove all expired keys from the database
                     for key in &expired_keys {
                         data_lock.remove(key);
+                        db_instance.log(LogRecord::Expire { key: key.clone() });
                     }
 
                     // Log the cleanup action if any keys were removed
@@ -41,7 +43,6 @@ pub async fn start_cleaner(db_map: DbMap) {
                         ));
                     }
                     drop(data_lock);
-                    db_instance.persist();
                 }
             }
 
@@ -0,0 +1,179 @@
+use std::sync::OnceLock;
+
+/// Default size (in bytes) a write-ahead log may grow to before it is
+/// compacted into a fresh snapshot.
+const DEFAULT_WAL_COMPACT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How often the write-ahead log is flushed to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    // fsync after every appended record (safest, slowest).
+    Always,
+    // fsync at most once per second from a background task.
+    EverySec,
+    // Never fsync explicitly; leave it to the operating system.
+    Never,
+}
+
+impl FsyncPolicy {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "everysec" => Some(Self::EverySec),
+            "never" | "no" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
+/// On-disk encoding used when writing database snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    // Pretty-printed JSON (`dbs/<name>.json`).
+    Json,
+    // Compact length-prefixed binary with checksum (`dbs/<name>.bin`).
+    Binary,
+}
+
+impl SnapshotFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "binary" | "bin" => Some(Self::Binary),
+            _ => None,
+        }
+    }
+
+    /// File extension of snapshots written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Binary => "bin",
+        }
+    }
+
+    /// The other supported format, checked when loading so existing
+    /// databases are converted transparently.
+    pub fn other(self) -> Self {
+        match self {
+            Self::Json => Self::Binary,
+            Self::Binary => Self::Json,
+        }
+    }
+}
+
+/// Snapshot a database once at least `changes` mutations happened and
+/// `seconds` elapsed since its last snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl SaveRule {
+    /// Parses a rule written as `<seconds>:<changes>`.
+    fn parse(s: &str) -> Option<Self> {
+        let (seconds, changes) = s.split_once(':')?;
+        Some(Self {
+            seconds: seconds.trim().parse().ok()?,
+            changes: changes.trim().parse().ok()?,
+        })
+    }
+}
+
+/// Rules used when none are given on the command line (same as Redis).
+const DEFAULT_SAVE_RULES: [SaveRule; 3] = [
+    SaveRule { seconds: 3600, changes: 1 },
+    SaveRule { seconds: 300, changes: 100 },
+    SaveRule { seconds: 60, changes: 10000 },
+];
+
+/// Server-wide settings, parsed once from the command line.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // Port the TCP listener binds to.
+    pub port: String,
+    // fsync policy for the per-database write-ahead logs.
+    pub appendfsync: FsyncPolicy,
+    // Log size in bytes after which the flusher compacts it into a snapshot.
+    pub wal_compact_size: u64,
+    // When the flusher snapshots modified databases.
+    pub save_rules: Vec<SaveRule>,
+    // Encoding of newly written snapshots.
+    pub snapshot_format: SnapshotFormat,
+    // Port of the HTTP/JSON API (disabled when not set).
+    pub http_port: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: "4000".to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            wal_compact_size: DEFAULT_WAL_COMPACT_SIZE,
+            save_rules: DEFAULT_SAVE_RULES.to_vec(),
+            snapshot_format: SnapshotFormat::Json,
+            http_port: None,
+        }
+    }
+}
+
+static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
+
+/// Parses command line arguments into the global server configuration.
+/// Usage: db-server [port] [--appendfsync always|everysec|never] [--wal-compact-size <bytes>]
+///                  [--save <seconds>:<changes>]... [--save none] [--snapshot-format json|binary]
+///                  [--http-port <port>]
+pub fn init(args: &[String]) -> Result<&'static ServerConfig, String> {
+    let mut config = ServerConfig::default();
+    let mut custom_save_rules: Option<Vec<SaveRule>> = None;
+    let mut iter = args.iter().skip(1);
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--appendfsync" => {
+                let value = iter.next().ok_or("Missing value for --appendfsync")?;
+                config.appendfsync = FsyncPolicy::parse(value)
+                    .ok_or(format!("Invalid fsync policy '{}' (use always, everysec or never)", value))?;
+            }
+            "--wal-compact-size" => {
+                let value = iter.next().ok_or("Missing value for --wal-compact-size")?;
+                config.wal_compact_size = value
+                    .parse()
+                    .map_err(|_| format!("Invalid WAL compaction size '{}'", value))?;
+            }
+            "--save" => {
+                let value = iter.next().ok_or("Missing value for --save")?;
+                let rules = custom_save_rules.get_or_insert_with(Vec::new);
+                if value != "none" {
+                    rules.push(SaveRule::parse(value).ok_or(format!(
+                        "Invalid save rule '{}' (use <seconds>:<changes> or none)",
+                        value
+                    ))?);
+                }
+            }
+            "--snapshot-format" => {
+                let value = iter.next().ok_or("Missing value for --snapshot-format")?;
+                config.snapshot_format = SnapshotFormat::parse(value)
+                    .ok_or(format!("Invalid snapshot format '{}' (use json or binary)", value))?;
+            }
+            "--http-port" => {
+                let value = iter.next().ok_or("Missing value for --http-port")?;
+                config.http_port = Some(value.to_string());
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            port => config.port = port.to_string(),
+        }
+    }
+
+    if let Some(rules) = custom_save_rules {
+        config.save_rules = rules;
+    }
+
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// Returns the global server configuration (defaults if `init` was never called).
+pub fn get() -> &'static ServerConfig {
+    CONFIG.get_or_init(ServerConfig::default)
+}
@@ -1,15 +1,26 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{self, Write};
 use std::path::Path;
 use serde::{Serialize, Deserialize};
 
+use crate::config::{self, SnapshotFormat};
+use crate::index::{IndexDefinition, Indexes};
 use crate::logger::log_info;
+use crate::snapshot;
+use crate::wal::{self, Change, LogRecord, Wal};
+use crate::zset::SortedSet;
+
+// Type alias for the keys of a database, kept in lexicographic order so they
+// can be scanned by prefix or range.
+pub type Keyspace = BTreeMap<String, ValueWithExpiry>;
 
 // Type alias for a database: a thread-safe, shared, mutable map of key-value pairs.
-pub type Db = Arc<Mutex<HashMap<String, ValueWithExpiry>>>;
+pub type Db = Arc<Mutex<Keyspace>>;
 
 // Type alias for managing multiple databases: each identified by a name and associated with a `DbInstance`.
 pub type DbMap = Arc<Mutex<HashMap<String, DbInstance>>>;
@@ -27,130 +38,517 @@ pub struct DbInstance {
     pub password: Option<String>,
     // Database name
     pub name: String,
+    // Append-only log of mutations since the last snapshot.
+    // Always locked after `data` when both are needed.
+    pub wal: Arc<Mutex<Wal>>,
+    // Number of mutations since the last snapshot.
+    changes: Arc<AtomicU64>,
+    // When the last snapshot was written.
+    last_save: Arc<Mutex<Instant>>,
+    // Highest revision handed out, either as the version of an entry or to
+    // a record of the write-ahead log.
+    revision: Arc<AtomicU64>,
+    // Secondary indexes, updated as mutations are logged. Always locked
+    // after `data` when both are needed.
+    pub indexes: Arc<Mutex<Indexes>>,
+    // Held for the whole of a snapshot so two are never written at once.
+    // Holds true once the database was dropped, so that a snapshot started
+    // by a connection or the flusher can't bring its files back.
+    saving: Arc<Mutex<bool>>,
+}
+
+// Version of the snapshot format written by `save_to_file`.
+// 0 (no version field): expires_at holds seconds remaining at write time.
+// 1: expires_at holds an absolute Unix timestamp in milliseconds.
+// 2: entries carry a version and modification time; the database its revision.
+// 3: entries hold typed values (a "value" string, a "list", a "hash", a "set",
+//    a "zset" or a "json" document).
+// 4: the database carries its secondary index definitions.
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+// Serializable version of ValueWithExpiry for snapshot storage
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SerializableValueWithExpiry {
+    #[serde(flatten)]
+    pub value: SerializableValue,
+    pub expires_at: Option<u64>, // Stored as Unix timestamp in milliseconds
+    #[serde(default)]
+    pub version: u64, // 0 for entries written before versions existed
+    #[serde(default)]
+    pub modified_at: u64, // Unix timestamp in milliseconds, 0 if unknown
+}
+
+// Serializable version of Value, stored under a field named after its type.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SerializableValue {
+    // Strings keep the "value" field they had before typed values existed.
+    #[serde(rename = "value", with = "json_bytes")]
+    String(Vec<u8>),
+    List(Vec<JsonBytes>),
+    Hash(BTreeMap<String, JsonBytes>),
+    Set(Vec<JsonBytes>),
+    // [member, score] pairs in score order.
+    Zset(Vec<(JsonBytes, f64)>),
+    Json(serde_json::Value),
+}
+
+/// A byte string in JSON, encoded like `json_bytes`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JsonBytes(#[serde(with = "json_bytes")] pub Vec<u8>);
+
+// Serializable version of database for snapshot storage
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SerializableDb {
+    #[serde(default)]
+    pub version: u32,
+    // Highest entry version handed out so far.
+    #[serde(default)]
+    pub revision: u64,
+    pub data: HashMap<String, SerializableValueWithExpiry>,
+    // Secondary index definitions; their contents are rebuilt on load.
+    #[serde(default)]
+    pub indexes: Vec<IndexDefinition>,
+    pub require_auth: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// JSON encoding for binary values: UTF-8 values are stored as plain strings
+/// (so existing files keep loading), anything else as `{"base64": "..."}`.
+pub mod json_bytes {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Encoded {
+        Text(String),
+        Base64 { base64: String },
+    }
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(value) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => Encoded::Base64 { base64: STANDARD.encode(value) }.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        match Encoded::deserialize(deserializer)? {
+            Encoded::Text(text) => Ok(text.into_bytes()),
+            Encoded::Base64 { base64 } => STANDARD.decode(base64).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Checks that a database name is safe to use as a file name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Reasons a database can't be created.
+#[derive(Debug)]
+pub enum CreateDbError {
+    InvalidName(String),
+    AlreadyExists(String),
+    // Hashing the password failed.
+    Internal,
 }
 
-// Serializable version of ValueWithExpiry for JSON storage
-#[derive(Serialize, Deserialize, Debug)]
-struct SerializableValueWithExpiry {
-    value: String,
-    expires_at: Option<u64>, // Stored as timestamp in seconds
+impl fmt::Display for CreateDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(f, "Error: Invalid database name '{}'", name),
+            Self::AlreadyExists(name) => write!(f, "Error: Database '{}' already exists", name),
+            Self::Internal => write!(f, "Error creating database"),
+        }
+    }
+}
+
+/// Creates a database, hashing the password if credentials are given, and
+/// registers it in `db_map`.
+pub fn create_database(db_map: &DbMap, name: &str, credentials: Option<(String, String)>) -> Result<DbInstance, CreateDbError> {
+    if !is_valid_name(name) {
+        return Err(CreateDbError::InvalidName(name.to_string()));
+    }
+    if DbInstance::exists_on_disk(name) {
+        return Err(CreateDbError::AlreadyExists(name.to_string()));
+    }
+
+    let db_instance = match credentials {
+        Some((username, password)) => {
+            let hashed_password = bcrypt::hash(&password, bcrypt::DEFAULT_COST).map_err(|e| {
+                log_info(&format!("⚠️ Error hashing password: {}", e));
+                CreateDbError::Internal
+            })?;
+            DbInstance::new(name.to_string(), true, Some(username), Some(hashed_password))
+        }
+        None => DbInstance::new(name.to_string(), false, None, None),
+    };
+
+    // Insert new database into shared state
+    db_map.lock().unwrap().insert(name.to_string(), db_instance.clone());
+    Ok(db_instance)
+}
+
+/// Unloads a database and deletes its files.
+pub fn drop_database(db_map: &DbMap, name: &str) -> io::Result<()> {
+    let unloaded = db_map.lock().unwrap().remove(name);
+    let mut dropped = unloaded.as_ref().map(|db_instance| db_instance.saving.lock().unwrap());
+    if let Err(e) = DbInstance::delete_files(name) {
+        // Keep serving the database if its files couldn't be removed
+        drop(dropped);
+        if let Some(db_instance) = unloaded {
+            db_map.lock().unwrap().insert(name.to_string(), db_instance);
+        }
+        return Err(e);
+    }
+    if let Some(dropped) = dropped.as_mut() {
+        **dropped = true;
+    }
+    Ok(())
+}
+
+/// Returns a database from memory, loading it from disk on first use.
+pub fn get_or_load(db_map: &DbMap, name: &str) -> Option<DbInstance> {
+    if !is_valid_name(name) {
+        return None;
+    }
+    let mut dbs = db_map.lock().unwrap();
+
+    // Try to get from memory first
+    if let Some(db) = dbs.get(name) {
+        return Some(db.clone());
+    }
+
+    // If not in memory, try to load from file
+    let db = DbInstance::load_from_file(name)?;
+    dbs.insert(name.to_string(), db.clone());
+    Some(db)
 }
 
-// Serializable version of database for JSON storage
-#[derive(Serialize, Deserialize, Debug)]
-struct SerializableDb {
-    data: HashMap<String, SerializableValueWithExpiry>,
-    require_auth: bool,
-    username: Option<String>,
-    password: Option<String>,
+/// Returns the current wall-clock time as Unix milliseconds.
+pub fn now_epoch_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Converts a monotonic deadline into an absolute Unix timestamp in milliseconds.
+pub fn instant_to_epoch_ms(instant: Instant) -> u64 {
+    let now = Instant::now();
+    let now_ms = now_epoch_ms();
+    match instant.checked_duration_since(now) {
+        Some(remaining) => now_ms.saturating_add(remaining.as_millis() as u64),
+        None => now_ms.saturating_sub(now.duration_since(instant).as_millis() as u64),
+    }
+}
+
+/// Converts an absolute Unix timestamp in milliseconds into a monotonic deadline.
+/// Timestamps already in the past map to "now", so the value reads as expired.
+pub fn epoch_ms_to_instant(ms: u64) -> Instant {
+    let now = Instant::now();
+    let remaining = ms.saturating_sub(now_epoch_ms());
+    now.checked_add(Duration::from_millis(remaining)).unwrap_or(now)
+}
+
+/// Returns the path of a database snapshot in the given format.
+fn snapshot_path(name: &str, format: SnapshotFormat) -> String {
+    format!("dbs/{}.{}", name, format.extension())
+}
+
+/// Returns the path where the previous snapshot of a database is kept.
+fn backup_path(name: &str, format: SnapshotFormat) -> String {
+    format!("{}.bak", snapshot_path(name, format))
+}
+
+/// Snapshot formats in lookup order: the configured one first.
+fn formats() -> [SnapshotFormat; 2] {
+    let configured = config::get().snapshot_format;
+    [configured, configured.other()]
+}
+
+/// Removes a file, treating a missing file as success.
+fn remove_if_exists(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the file at `path` with `contents` so that a crash at any point
+/// leaves either the old or the new file intact. The old file, if any, is
+/// kept at `backup`.
+fn write_atomic(path: &str, backup: &str, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+
+    // Write and flush the new contents next to the live file
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(contents)?;
+    tmp.sync_all()?;
+    drop(tmp);
+
+    // Keep the current file as the backup without ever removing the live copy
+    if Path::new(path).exists() {
+        remove_if_exists(backup)?;
+        if fs::hard_link(path, backup).is_err() {
+            fs::copy(path, backup)?;
+        }
+    }
+
+    fs::rename(&tmp_path, path)?;
+
+    // Make the rename itself durable
+    let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
+}
+
+/// Reads and parses a snapshot file. Returns the snapshot together with the
+/// file's modification time in Unix milliseconds.
+fn read_snapshot(path: &str, format: SnapshotFormat) -> io::Result<(SerializableDb, u64)> {
+    let contents = fs::read(path)?;
+    let serialized = snapshot::decode(&contents, format)?;
+
+    let modified_ms = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_else(now_epoch_ms);
+
+    Ok((serialized, modified_ms))
 }
 
 impl DbInstance {
     /// Creates a new database instance and persists it to a file.
     pub fn new(name: String, require_auth: bool, username: Option<String>, password: Option<String>) -> Self {
         // Create the dbs directory if it doesn't exist
-        fs::create_dir_all("dbs").unwrap_or_else(|_| ());
-        
+        fs::create_dir_all("dbs").unwrap_or(());
+
+        let wal = Wal::open(&name).expect("Failed to open database log");
         let instance = Self {
-            data: Arc::new(Mutex::new(HashMap::new())),
+            data: Arc::new(Mutex::new(Keyspace::new())),
             require_auth,
             username,
             password,
             name,
+            wal: Arc::new(Mutex::new(wal)),
+            changes: Arc::new(AtomicU64::new(0)),
+            last_save: Arc::new(Mutex::new(Instant::now())),
+            revision: Arc::new(AtomicU64::new(0)),
+            indexes: Arc::new(Mutex::new(Indexes::default())),
+            saving: Arc::new(Mutex::new(false)),
         };
-        
-        // Save empty database to file
+
+        // Save empty database to file (this also sets aside any stale log)
         instance.save_to_file().expect("Failed to save new database");
         instance
     }
 
-    /// Loads a database from file
+    /// Checks a username and password against the database's credentials.
+    /// bcrypt is slow by design, so the hash is verified on the blocking pool.
+    pub async fn check_credentials(&self, username: &str, password: &str) -> Result<bool, bcrypt::BcryptError> {
+        let password = password.to_string();
+        let hash = self.password.clone().unwrap_or_default();
+        let password_ok = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash))
+            .await
+            .map_err(|e| bcrypt::BcryptError::Io(io::Error::other(e)))??;
+        Ok(password_ok && self.username.as_deref() == Some(username))
+    }
+
+    /// Checks whether a database with this name exists on disk (in any format).
+    pub fn exists_on_disk(name: &str) -> bool {
+        formats().iter().any(|&format| {
+            Path::new(&snapshot_path(name, format)).exists()
+                || Path::new(&backup_path(name, format)).exists()
+        })
+    }
+
+    /// Removes the snapshots of one format.
+    fn delete_snapshots(name: &str, format: SnapshotFormat) -> io::Result<()> {
+        let path = snapshot_path(name, format);
+        remove_if_exists(&path)?;
+        remove_if_exists(&backup_path(name, format))?;
+        remove_if_exists(&format!("{}.tmp", path))
+    }
+
+    /// Removes the snapshots and write-ahead log of a database.
+    pub fn delete_files(name: &str) -> io::Result<()> {
+        if !Self::exists_on_disk(name) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no snapshot found"));
+        }
+
+        for format in formats() {
+            Self::delete_snapshots(name, format)?;
+        }
+        remove_if_exists(&wal::backup_log_path(name))?;
+        remove_if_exists(&wal::log_path(name))
+    }
+
+    /// Loads a database from its snapshot and replays its write-ahead log.
+    /// Falls back to the previous snapshot if the current one is missing or
+    /// corrupt, and to the other format only if neither can be read.
+    /// Snapshots in a format other than the configured one are converted.
     pub fn load_from_file(name: &str) -> Option<Self> {
-        let path = format!("dbs/{}.json", name);
-        if !Path::new(&path).exists() {
+        if !Self::exists_on_disk(name) {
             return None;
         }
 
-        let mut file = File::open(&path).ok()?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).ok()?;
-        
-        let serialized: SerializableDb = serde_json::from_str(&contents).ok()?;
-        
-        let mut data = HashMap::new();
-        for (key, val) in serialized.data {
-            let expires_at = val.expires_at.map(|ts| {
-                Instant::now() + Duration::from_secs(ts.saturating_sub(
-                    Instant::now().elapsed().as_secs()
-                ))
-            });
-            
-            data.insert(key, ValueWithExpiry {
-                value: val.value,
-                expires_at,
-            });
-        }
-
-        Some(Self {
+        // The configured format's current snapshot, then its backup, before
+        // either file of the other format: a leftover snapshot in the other
+        // format is older than anything written since switching formats
+        let candidates = formats().into_iter().flat_map(|format| {
+            [(snapshot_path(name, format), format, false), (backup_path(name, format), format, true)]
+        });
+
+        let mut loaded = None;
+        for (path, format, is_backup) in candidates {
+            if !Path::new(&path).exists() {
+                continue;
+            }
+            match read_snapshot(&path, format) {
+                Ok((snapshot, modified_ms)) => {
+                    if is_backup {
+                        log_info(&format!("⚠️ Loading '{}' from backup snapshot {}", name, path));
+                    }
+                    loaded = Some((snapshot, modified_ms, format, is_backup));
+                    break;
+                }
+                Err(e) => log_info(&format!("⚠️ Failed to read snapshot {}: {}", path, e)),
+            }
+        }
+        let (serialized, modified_ms, format, from_backup) = loaded?;
+
+        let mut data = Keyspace::new();
+        for (key, mut val) in serialized.data {
+            // Unversioned snapshots store seconds remaining, counted from when
+            // the file was written; rebase them onto its modification time.
+            if serialized.version == 0 {
+                val.expires_at = val
+                    .expires_at
+                    .map(|secs| modified_ms.saturating_add(secs.saturating_mul(1000)));
+            }
+            data.insert(key, ValueWithExpiry::from_serializable(val));
+        }
+
+        // Apply mutations logged after the snapshot was taken. The backup
+        // snapshot first needs the log that led up to the current snapshot.
+        let mut revision = serialized.revision;
+        let replayed = if from_backup {
+            wal::replay_backup(name, &mut data, &mut revision)
+                .and_then(|n| wal::replay(name, &mut data, &mut revision).map(|m| n + m))
+        } else {
+            wal::replay(name, &mut data, &mut revision)
+        };
+        let replayed = match replayed {
+            Ok(count) => count,
+            Err(e) => {
+                // Serving the database without the rest of its log would lose writes
+                log_info(&format!("⚠️ Not loading '{}': failed to replay its log: {}", name, e));
+                return None;
+            }
+        };
+        let wal = Wal::open(name).ok()?;
+
+        // Never hand out a version again, even if the snapshot predates the
+        // log; expired keys count too, so their versions can't come back
+        let revision = data
+            .values()
+            .map(|val| val.version)
+            .fold(revision, u64::max);
+
+        // Drop keys whose deadline passed while the database was on disk
+        let before = data.len();
+        data.retain(|_, val| !val.is_expired());
+        let dropped = before - data.len();
+
+        let indexes = Indexes::new(serialized.indexes, &data);
+        let instance = Self {
             data: Arc::new(Mutex::new(data)),
             require_auth: serialized.require_auth,
             username: serialized.username,
             password: serialized.password,
             name: name.to_string(),
-        })
-    }
+            wal: Arc::new(Mutex::new(wal)),
+            changes: Arc::new(AtomicU64::new(0)),
+            last_save: Arc::new(Mutex::new(Instant::now())),
+            revision: Arc::new(AtomicU64::new(revision)),
+            indexes: Arc::new(Mutex::new(indexes)),
+            saving: Arc::new(Mutex::new(false)),
+        };
 
-    /// Saves the database to file
-    pub fn save_to_file(&self) -> std::io::Result<()> {
-        let path = format!("dbs/{}.json", self.name);
-        
-        let data = self.data.lock().unwrap();
-        
-        let mut serialized_data = HashMap::new();
-        for (key, val) in data.iter() {
-            let expires_at = val.expires_at.map(|instant| {
-                instant.checked_duration_since(Instant::now())
-                    .map(|dur| dur.as_secs())
-                    .unwrap_or(0)
-            });
-            
-            serialized_data.insert(
-                key.clone(),
-                SerializableValueWithExpiry {
-                    value: val.value.clone(),
-                    expires_at,
+        if replayed > 0 {
+            log_info(&format!("Replayed {} logged mutations into '{}'", replayed, name));
+        }
+        if dropped > 0 {
+            log_info(&format!("Dropped {} keys that expired on disk from '{}'", dropped, name));
+        }
+
+        let converted = format != config::get().snapshot_format;
+
+        // Fold the replayed log, dropped keys and any format migration into a fresh snapshot
+        if replayed > 0 || dropped > 0 || from_backup || converted || serialized.version != SNAPSHOT_VERSION {
+            match instance.save_to_file() {
+                // The old format is only removed once the new snapshot is safely on disk
+                Ok(()) if converted => {
+                    log_info(&format!(
+                        "Converted '{}' from {} to {} snapshot",
+                        name,
+                        format.extension(),
+                        config::get().snapshot_format.extension()
+                    ));
+                    if let Err(e) = Self::delete_snapshots(name, format) {
+                        log_info(&format!("⚠️ Failed to remove old snapshot of '{}': {}", name, e));
+                    }
                 }
-            );
-        }
-        
-        
-        let serialized = SerializableDb {
-            data: serialized_data,
-            require_auth: self.require_auth,
-            username: self.username.clone(),
-            password: self.password.clone(),
-        };
-        
-        
-        let json = match serde_json::to_string_pretty(&serialized) {
-            Ok(j) => j,
-            Err(e) => {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
-            }
-        };
-        
-        
-        match File::create(&path) {
-            Ok(mut file) => {
-                if let Err(e) = file.write_all(json.as_bytes()) {
-                    return Err(e);
+                Ok(()) => {}
+                Err(e) => {
+                    log_info(&format!("⚠️ Failed to persist database '{}': {}", name, e));
                 }
-                Ok(())
-            }
-            Err(e) => {
-                Err(e)
             }
         }
+        Some(instance)
+    }
+
+    /// Atomically saves the database to file and rotates its write-ahead log
+    pub fn save_to_file(&self) -> std::io::Result<()> {
+        let dropped = self.saving.lock().unwrap();
+        if *dropped {
+            return Ok(());
+        }
+        let format = config::get().snapshot_format;
+        let path = snapshot_path(&self.name, format);
+
+        // Only copy the data under the lock; encoding and writing happen
+        // without it. The copy holds every record logged so far, which later
+        // replays skip by revision, so the log is split where it was taken.
+        let (serialized, covered, changes) = {
+            let data = self.data.lock().unwrap();
+            let serialized = SerializableDb {
+                version: SNAPSHOT_VERSION,
+                revision: self.revision.load(Ordering::SeqCst),
+                data: data.iter().map(|(key, val)| (key.clone(), val.to_serializable())).collect(),
+                indexes: self.indexes.lock().unwrap().definitions(),
+                require_auth: self.require_auth,
+                username: self.username.clone(),
+                password: self.password.clone(),
+            };
+            (serialized, self.wal.lock().unwrap().size(), self.changes())
+        };
+
+        let bytes = snapshot::encode(&serialized, format)?;
+        write_atomic(&path, &backup_path(&self.name, format), &bytes)?;
+
+        self.wal.lock().unwrap().rotate(covered)?;
+
+        self.changes.fetch_sub(changes, Ordering::SeqCst);
+        *self.last_save.lock().unwrap() = Instant::now();
+        Ok(())
     }
 
     pub fn persist(&self) {
@@ -158,22 +556,174 @@ impl DbInstance {
             log_info(&format!("⚠️ Failed to persist database '{}': {}", self.name, e));
         }
     }
+
+    /// Appends a mutation to the write-ahead log at the next revision and
+    /// updates the secondary indexes to match.
+    /// Callers hold the `data` lock so the log order matches the applied order.
+    pub fn log(&self, record: LogRecord) {
+        self.indexes.lock().unwrap().apply(&record);
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        let rev = self.revision.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Err(e) = self.wal.lock().unwrap().append(rev, &record) {
+            log_info(&format!("⚠️ Failed to log mutation to '{}': {}", self.name, e));
+        }
+    }
+
+    /// Logs several mutations that were applied together as one batch.
+    pub fn log_batch(&self, mut records: Vec<LogRecord>) {
+        match records.len() {
+            0 => {}
+            1 => self.log(records.pop().unwrap()),
+            count => {
+                self.changes.fetch_add(count as u64 - 1, Ordering::SeqCst);
+                self.log(LogRecord::Batch { records });
+            }
+        }
+    }
+
+    /// Gives `entry` the next version of this database and marks it as
+    /// modified now. Callers hold the `data` lock.
+    pub fn stamp(&self, entry: &mut ValueWithExpiry) {
+        entry.version = self.revision.fetch_add(1, Ordering::SeqCst) + 1;
+        entry.modified_at = now_epoch_ms();
+    }
+
+    /// Stamps `entry` with a new version and logs that `key` now holds it.
+    pub fn log_set(&self, key: &str, entry: &mut ValueWithExpiry) {
+        self.stamp(entry);
+        self.log(LogRecord::Set {
+            key: key.to_string(),
+            entry: entry.to_serializable(),
+        });
+    }
+
+    /// Stamps `entry` with a new version and logs `change`, which was just
+    /// applied to the collection it holds at `key`.
+    pub fn log_change(&self, key: &str, entry: &mut ValueWithExpiry, change: Change) {
+        self.stamp(entry);
+        // The record doesn't hold the new value, so index it from the entry
+        self.indexes.lock().unwrap().update(key, &entry.value);
+        self.log(LogRecord::Update {
+            key: key.to_string(),
+            version: entry.version,
+            modified_at: entry.modified_at,
+            change,
+        });
+    }
+
+    /// Number of mutations since the last snapshot.
+    pub fn changes(&self) -> u64 {
+        self.changes.load(Ordering::SeqCst)
+    }
+
+    /// When the last snapshot was written.
+    pub fn last_save(&self) -> Instant {
+        *self.last_save.lock().unwrap()
+    }
+
+    /// Size of the write-ahead log in bytes.
+    pub fn wal_size(&self) -> u64 {
+        self.wal.lock().unwrap().size()
+    }
+}
+
+/// A value stored at a key. Commands only operate on their own type and
+/// reply with a WRONG_TYPE error otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    // A byte string (SET, GET, INCR, ...).
+    String(Vec<u8>),
+    // A list of byte strings (LPUSH, LRANGE, ...). Never empty: the key is
+    // removed with its last element.
+    List(VecDeque<Vec<u8>>),
+    // Field names mapped to byte strings (HSET, HGET, ...), in field order.
+    // Never empty, like lists.
+    Hash(BTreeMap<String, Vec<u8>>),
+    // Distinct byte strings (SADD, SMEMBERS, ...), kept sorted so replies
+    // are stable. Never empty.
+    Set(BTreeSet<Vec<u8>>),
+    // Members ordered by score (ZADD, ZRANGE, ...). Never empty.
+    SortedSet(SortedSet),
+    // A JSON document (JSON.SET, JSON.GET, ...).
+    Json(serde_json::Value),
+}
+
+impl Value {
+    /// Name of the type as reported by TYPE.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Json(_) => "json",
+        }
+    }
+
+    /// Whether this is a collection with no elements left, whose key should
+    /// be deleted.
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            Self::String(_) | Self::Json(_) => false,
+            Self::List(items) => items.is_empty(),
+            Self::Hash(fields) => fields.is_empty(),
+            Self::Set(members) => members.is_empty(),
+            Self::SortedSet(set) => set.is_empty(),
+        }
+    }
+
+    fn to_serializable(&self) -> SerializableValue {
+        match self {
+            Self::String(bytes) => SerializableValue::String(bytes.clone()),
+            Self::List(items) => SerializableValue::List(items.iter().cloned().map(JsonBytes).collect()),
+            Self::Hash(fields) => SerializableValue::Hash(
+                fields.iter().map(|(field, value)| (field.clone(), JsonBytes(value.clone()))).collect(),
+            ),
+            Self::Set(members) => SerializableValue::Set(members.iter().cloned().map(JsonBytes).collect()),
+            Self::SortedSet(set) => {
+                SerializableValue::Zset(set.iter().map(|(member, score)| (JsonBytes(member.to_vec()), score)).collect())
+            }
+            Self::Json(doc) => SerializableValue::Json(doc.clone()),
+        }
+    }
+
+    fn from_serializable(value: SerializableValue) -> Self {
+        match value {
+            SerializableValue::String(bytes) => Self::String(bytes),
+            SerializableValue::List(items) => Self::List(items.into_iter().map(|item| item.0).collect()),
+            SerializableValue::Hash(fields) => {
+                Self::Hash(fields.into_iter().map(|(field, value)| (field, value.0)).collect())
+            }
+            SerializableValue::Set(members) => Self::Set(members.into_iter().map(|member| member.0).collect()),
+            SerializableValue::Zset(members) => {
+                Self::SortedSet(members.into_iter().map(|(member, score)| (member.0, score)).collect())
+            }
+            SerializableValue::Json(doc) => Self::Json(doc),
+        }
+    }
 }
 
 /// Represents a value in the database along with its optional expiration time.
 #[derive(Debug, Clone)]
 pub struct ValueWithExpiry {
     // The actual value stored in the DB.
-    pub value: String,
+    pub value: Value,
     // When the key should expire (if any).
     pub expires_at: Option<Instant>, 
+    // Version assigned by `DbInstance::stamp` on every write; increases
+    // across the whole database, so a recreated key never reuses one.
+    pub version: u64,
+    // When the entry was last written, as Unix milliseconds.
+    pub modified_at: u64,
 }
 
 impl ValueWithExpiry {
     /// Creates a new `ValueWithExpiry` with optional time-to-live.
-    pub fn new(value: String, ttl: Option<Duration>) -> Self {
-        // Calculate the expiry time if TTL is provided.
-        let expires_at = ttl.map(|d| Instant::now() + d);
+    pub fn new(value: Value, ttl: Option<Duration>) -> Self {
+        // Calculate the expiry time if TTL is provided. Commands reject TTLs
+        // too large to represent; any that get here are treated as no TTL.
+        let expires_at = ttl.and_then(|d| Instant::now().checked_add(d));
 
         // Log key insertion with TTL status.
         let msg = match expires_at {
@@ -182,7 +732,12 @@ impl ValueWithExpiry {
         };
         log_info(&msg);
 
-        Self { value, expires_at }
+        Self {
+            value,
+            expires_at,
+            version: 0,
+            modified_at: now_epoch_ms(),
+        }
     }
 
     /// Checks if the value has expired based on current time.
@@ -191,4 +746,136 @@ impl ValueWithExpiry {
             .map(|time| Instant::now() > time)
             .unwrap_or(false)
     }
-}
\ No newline at end of file
+
+    /// Converts to the on-disk representation.
+    pub fn to_serializable(&self) -> SerializableValueWithExpiry {
+        SerializableValueWithExpiry {
+            value: self.value.to_serializable(),
+            expires_at: self.expires_at.map(instant_to_epoch_ms),
+            version: self.version,
+            modified_at: self.modified_at,
+        }
+    }
+
+    /// Rebuilds a value from its on-disk representation.
+    pub fn from_serializable(val: SerializableValueWithExpiry) -> Self {
+        Self {
+            value: Value::from_serializable(val.value),
+            expires_at: val.expires_at.map(epoch_ms_to_instant),
+            version: val.version,
+            modified_at: val.modified_at,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use crate::parser;
+    use crate::reply::Reply;
+
+    /// A database on disk used by one test, deleted when dropped.
+    pub struct TestDb {
+        pub name: String,
+        pub db: Option<Arc<DbInstance>>,
+        pub db_map: DbMap,
+    }
+
+    impl TestDb {
+        /// Creates an empty database called `name`, replacing any leftovers.
+        pub fn new(name: &str) -> Self {
+            let _ = DbInstance::delete_files(name);
+            let instance = DbInstance::new(name.to_string(), false, None, None);
+            let db_map: DbMap = Arc::new(Mutex::new(HashMap::new()));
+            db_map.lock().unwrap().insert(name.to_string(), instance.clone());
+            Self { name: name.to_string(), db: Some(Arc::new(instance)), db_map }
+        }
+
+        /// Runs a command and returns its reply.
+        pub fn reply(&self, command: &str) -> Reply {
+            parser::parse_statement(command, Vec::new(), &self.db, &self.db_map)
+        }
+
+        /// Runs a command and returns its reply as the line protocol prints it.
+        pub fn run(&self, command: &str) -> String {
+            String::from_utf8_lossy(&self.reply(command).to_text()).into_owned()
+        }
+
+        /// Loads the database again from its snapshot and log, as after a restart.
+        pub fn reload(&mut self) {
+            let instance = DbInstance::load_from_file(&self.name).expect("database should load");
+            self.db_map.lock().unwrap().insert(self.name.clone(), instance.clone());
+            self.db = Some(Arc::new(instance));
+        }
+
+        pub fn instance(&self) -> &DbInstance {
+            self.db.as_ref().unwrap()
+        }
+
+        /// The version of a key, if it exists.
+        pub fn version(&self, key: &str) -> Option<u64> {
+            self.instance().data.lock().unwrap().get(key).map(|entry| entry.version)
+        }
+
+        /// Makes saving snapshots fail, by putting a directory where they
+        /// are written first, until `repair_snapshots` is called.
+        pub fn break_snapshots(&self) {
+            for format in formats() {
+                fs::create_dir_all(format!("{}.tmp", snapshot_path(&self.name, format))).unwrap();
+            }
+        }
+
+        pub fn repair_snapshots(&self) {
+            for format in formats() {
+                let _ = fs::remove_dir(format!("{}.tmp", snapshot_path(&self.name, format)));
+            }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            self.repair_snapshots();
+            let _ = DbInstance::delete_files(&self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::TestDb;
+    use super::*;
+
+    #[test]
+    fn versions_are_not_reused_after_a_delete_and_restart() {
+        let mut db = TestDb::new("db_test_versions_after_delete");
+        db.run("SET(\"k\",\"a\")");
+        db.run("SET(\"k\",\"b\")");
+        let old = db.version("k").unwrap();
+        db.run("DEL(\"k\")");
+
+        db.reload();
+        db.run("SET(\"k\",\"c\")");
+        // The delete took a revision of its own
+        assert!(db.version("k").unwrap() > old + 1);
+        assert!(matches!(
+            db.reply(&format!("SET(\"k\",\"d\",VERSION,\"{}\")", old)),
+            crate::reply::Reply::Error(crate::reply::ErrorCode::VersionMismatch, _)
+        ));
+    }
+
+    #[test]
+    fn versions_of_keys_that_expired_on_disk_are_not_reused() {
+        let mut db = TestDb::new("db_test_versions_after_expiry");
+        // A record from before log records carried their revision
+        let mut entry = ValueWithExpiry::new(Value::String(b"v".to_vec()), Some(Duration::from_millis(1)));
+        entry.version = 40;
+        let record = LogRecord::Set { key: "k".to_string(), entry: entry.to_serializable() };
+        db.instance().wal.lock().unwrap().append(0, &record).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        db.reload();
+        assert_eq!(db.version("k"), None);
+        db.run("SET(\"k\",\"new\")");
+        assert!(db.version("k").unwrap() > 40);
+    }
+}
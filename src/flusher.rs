@@ -0,0 +1,62 @@
+use std::time::Duration;
+use tokio::time::sleep;
+
+use crate::config;
+use crate::db::{DbInstance, DbMap};
+use crate::logger::log_info;
+
+/// Starts a background async task that snapshots modified databases.
+/// Every second each database in `db_map` is checked against the configured
+/// save rules ("save after N changes in M seconds"); a database is also
+/// snapshotted once its write-ahead log outgrows the configured size.
+/// Databases without changes since their last snapshot are never rewritten.
+pub async fn start_flusher(db_map: DbMap) {
+    tokio::spawn(async move {
+        loop {
+            // Release the map before saving so USE and CREATE never wait on a snapshot
+            let databases: Vec<(String, DbInstance)> = db_map
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(db_name, db_instance)| (db_name.clone(), db_instance.clone()))
+                .collect();
+
+            // Snapshots block on disk I/O, so they run off the async workers
+            let flushed = tokio::task::spawn_blocking(move || {
+                for (db_name, db_instance) in databases {
+                    flush_if_due(&db_name, &db_instance);
+                }
+            })
+            .await;
+            if let Err(e) = flushed {
+                log_info(&format!("⚠️ Flusher failed: {}", e));
+            }
+
+            sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+/// Snapshots a database if one of the save rules matches or its log is too large.
+fn flush_if_due(db_name: &str, db_instance: &DbInstance) {
+    let config = config::get();
+    let changes = db_instance.changes();
+    if changes == 0 {
+        return;
+    }
+
+    let elapsed = db_instance.last_save().elapsed().as_secs();
+    let rule_matched = config
+        .save_rules
+        .iter()
+        .any(|rule| elapsed >= rule.seconds && changes >= rule.changes);
+    let wal_too_large = db_instance.wal_size() > config.wal_compact_size;
+
+    if rule_matched || wal_too_large {
+        log_info(&format!(
+            "💾 Saving '{}' ({} changes in {} seconds)",
+            db_name, changes, elapsed
+        ));
+        db_instance.persist();
+    }
+}
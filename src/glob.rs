@@ -0,0 +1,164 @@
+// =======================================================
+// 🧠 INFO: Glob Patterns
+// =======================================================
+// Redis-style glob matching for KEYS and SCAN ... MATCH:
+// - `*` matches any sequence of characters (including none)
+// - `?` matches exactly one character
+// - `[abc]`, `[a-z]` match one character from a set; `[^abc]` or `[!abc]`
+//   match one character not in it
+// - `\x` matches `x` literally
+
+/// Checks whether `text` matches the glob `pattern`.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+            continue;
+        }
+        if p < pattern.len()
+            && let Some(next) = match_one(&pattern, p, text[t])
+        {
+            p = next;
+            t += 1;
+            continue;
+        }
+        // Let the last `*` swallow one more character and retry
+        match backtrack {
+            Some((star, matched)) => {
+                backtrack = Some((star, matched + 1));
+                p = star + 1;
+                t = matched + 1;
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches the single-character token at `pattern[p]` against `c`, returning
+/// the position of the next token on success.
+fn match_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match pattern[p] {
+        '?' => Some(p + 1),
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        '[' => match match_class(pattern, p, c) {
+            Some((matched, next)) => matched.then_some(next),
+            // An unterminated class is a literal '['
+            None => (c == '[').then_some(p + 1),
+        },
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+/// Matches the character class starting at `pattern[start]` (a '[') against
+/// `c`. Returns whether it matched and the position after the closing ']',
+/// or `None` if the class is not terminated.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut p = start + 1;
+    let negated = matches!(pattern.get(p), Some('^' | '!'));
+    if negated {
+        p += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(p)?;
+        // A ']' right after the opening bracket is part of the set
+        if low == ']' && !first {
+            return Some((matched != negated, p + 1));
+        }
+        first = false;
+        if low == '\\' {
+            p += 1;
+            low = *pattern.get(p)?;
+        }
+
+        // Ranges like a-z (a trailing '-' is literal)
+        if pattern.get(p + 1) == Some(&'-') && pattern.get(p + 2).is_some_and(|&high| high != ']') {
+            let mut high = pattern[p + 2];
+            p += 2;
+            if high == '\\' {
+                p += 1;
+                high = *pattern.get(p)?;
+            }
+            let (low, high) = if low <= high { (low, high) } else { (high, low) };
+            matched |= (low..=high).contains(&c);
+        } else {
+            matched |= low == c;
+        }
+        p += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("user:*", "user:"));
+        assert!(matches("user:*", "user:42"));
+        assert!(!matches("user:*", "users"));
+        assert!(matches("a**", "a"));
+        assert!(matches("*b*", "abc"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(matches("h?llo", "héllo"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(!matches("", "a"));
+        assert!(matches("", ""));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(matches("h[c-a]llo", "hbllo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[!e]llo", "hello"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("[a\\-z]", "-"));
+        assert!(!matches("[a\\-z]", "b"));
+    }
+
+    #[test]
+    fn bracket_edge_cases() {
+        // A ']' right after '[' is a member, so "[]" never closes
+        assert!(matches("[]", "[]"));
+        assert!(!matches("[]", "]"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[]a]", "a"));
+        assert!(matches("[^]]", "a"));
+        assert!(!matches("[^]]", "]"));
+        // Unterminated classes are a literal '['
+        assert!(matches("[abc", "[abc"));
+        assert!(!matches("[abc", "a"));
+        assert!(matches("x[", "x["));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("\\?x", "?x"));
+        assert!(matches("a\\[b]", "a[b]"));
+        // A trailing backslash matches itself
+        assert!(matches("a\\", "a\\"));
+        assert!(!matches("a\\", "a"));
+        assert!(matches("*\\", "end\\"));
+    }
+}
@@ -0,0 +1,350 @@
+// =======================================================
+// 🧠 INFO: Hash Commands
+// =======================================================
+// Hashes are stored as `Value::Hash`: UTF-8 field names mapped to byte string
+// values. Like lists they are never left empty, and mutations log only the
+// fields they set or deleted.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::db::{DbInstance, JsonBytes, Keyspace, Value, ValueWithExpiry};
+use crate::parser::{key_arg, log_change, no_database, parse_integer, purge_if_expired, usage, wrong_type};
+use crate::reply::{ErrorCode, Reply};
+use crate::wal::Change;
+
+type Hash = BTreeMap<String, Vec<u8>>;
+
+/// HSET("key","field","value",...) - creates the hash if needed and replies
+/// with the number of fields that were added (not just updated); the TTL of
+/// an existing hash is kept
+pub fn set(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return usage("HSET(\"key\",\"field\",\"value\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    // Later values of a field given twice win, as if set one after the other
+    let mut pairs = BTreeMap::new();
+    while let (Some(field), Some(value)) = (args.next(), args.next()) {
+        match field_arg(field) {
+            Ok(field) => pairs.insert(field, value),
+            Err(e) => return e,
+        };
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let entry = db
+                .entry(key.clone())
+                .or_insert_with(|| ValueWithExpiry::new(Value::Hash(BTreeMap::new()), None));
+            let Value::Hash(fields) = &mut entry.value else {
+                return wrong_type();
+            };
+            let mut added = 0;
+            for (field, value) in &pairs {
+                if fields.insert(field.clone(), value.clone()).is_none() {
+                    added += 1;
+                }
+            }
+            let fields = pairs.into_iter().map(|(field, value)| (field, JsonBytes(value))).collect();
+            db_instance.log_change(&key, entry, Change::HashSet { fields });
+            Reply::Int(added as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// HGET("key","field") - replies with the value of a field, or nil if the
+/// field or key doesn't exist
+pub fn get(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("HGET(\"key\",\"field\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let field = match field_arg(args.next().unwrap()) {
+        Ok(field) => field,
+        Err(e) => return e,
+    };
+
+    with_hash(&key, current_db_instance, |fields| {
+        fields.and_then(|fields| fields.get(&field)).map_or(Reply::Nil, |value| Reply::Value(value.clone()))
+    })
+}
+
+/// HMGET("key","field",...) - replies with the value of each field, nil for
+/// missing ones
+pub fn mget(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 2 {
+        return usage("HMGET(\"key\",\"field\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let names = match args.map(field_arg).collect::<Result<Vec<_>, _>>() {
+        Ok(names) => names,
+        Err(e) => return e,
+    };
+
+    with_hash(&key, current_db_instance, |fields| {
+        Reply::Array(
+            names
+                .iter()
+                .map(|field| {
+                    fields.and_then(|fields| fields.get(field)).map_or(Reply::Nil, |value| Reply::Value(value.clone()))
+                })
+                .collect(),
+        )
+    })
+}
+
+/// HDEL("key","field",...) - replies with the number of fields removed,
+/// deleting the key if none are left
+pub fn del(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 2 {
+        return usage("HDEL(\"key\",\"field\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let names = match args.map(field_arg).collect::<Result<Vec<_>, _>>() {
+        Ok(names) => names,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let fields = match hash_mut(&mut db, db_instance, &key) {
+                Ok(Some(fields)) => fields,
+                Ok(None) => return Reply::Int(0),
+                Err(e) => return e,
+            };
+
+            let removed: Vec<_> = names.into_iter().filter(|field| fields.remove(field).is_some()).collect();
+            let count = removed.len();
+            if count > 0 {
+                log_change(&mut db, db_instance, &key, Change::HashDel { fields: removed });
+            }
+            Reply::Int(count as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// HGETALL("key") - replies with every field and its value, in field order
+/// (empty if the key doesn't exist)
+pub fn get_all(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("HGETALL(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    with_hash(&key, current_db_instance, |fields| {
+        Reply::Map(
+            fields
+                .into_iter()
+                .flatten()
+                .map(|(field, value)| (Reply::Value(field.clone().into_bytes()), Reply::Value(value.clone())))
+                .collect(),
+        )
+    })
+}
+
+/// HINCRBY("key","field","n") - adds an integer to a field (a missing field
+/// counts as 0) and replies with the new value
+pub fn incr_by(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 3 {
+        return usage("HINCRBY(\"key\",\"field\",\"increment\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let field = match field_arg(args.next().unwrap()) {
+        Ok(field) => field,
+        Err(e) => return e,
+    };
+    let Some(delta) = parse_integer(&args.next().unwrap()) else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Increment must be an integer");
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let current = match db.get(&key).map(|entry| &entry.value) {
+                Some(Value::Hash(fields)) => fields.get(&field),
+                Some(_) => return wrong_type(),
+                None => None,
+            };
+            let current = match current {
+                Some(value) => match parse_integer(value) {
+                    Some(current) => current,
+                    None => return Reply::error(ErrorCode::InvalidArgument, "Error: Hash value is not an integer"),
+                },
+                None => 0,
+            };
+            let Some(new) = current.checked_add(delta) else {
+                return Reply::error(ErrorCode::InvalidArgument, "Error: Increment would overflow");
+            };
+
+            let entry = db
+                .entry(key.clone())
+                .or_insert_with(|| ValueWithExpiry::new(Value::Hash(BTreeMap::new()), None));
+            let value = new.to_string().into_bytes();
+            if let Value::Hash(fields) = &mut entry.value {
+                fields.insert(field.clone(), value.clone());
+            }
+            let fields = BTreeMap::from([(field, JsonBytes(value))]);
+            db_instance.log_change(&key, entry, Change::HashSet { fields });
+            Reply::Int(new)
+        }
+        None => no_database(),
+    }
+}
+
+/// HEXISTS("key","field") - replies 1 if the field exists, 0 otherwise
+pub fn exists(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("HEXISTS(\"key\",\"field\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let field = match field_arg(args.next().unwrap()) {
+        Ok(field) => field,
+        Err(e) => return e,
+    };
+
+    with_hash(&key, current_db_instance, |fields| {
+        Reply::Int(fields.is_some_and(|fields| fields.contains_key(&field)) as i64)
+    })
+}
+
+/// HLEN("key") - replies with the number of fields (0 if the key doesn't exist)
+pub fn len(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("HLEN(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    with_hash(&key, current_db_instance, |fields| Reply::Int(fields.map_or(0, |fields| fields.len() as i64)))
+}
+
+/// Converts a field argument to a string; like keys, fields must be valid UTF-8.
+fn field_arg(arg: Vec<u8>) -> Result<String, Reply> {
+    String::from_utf8(arg).map_err(|_| Reply::error(ErrorCode::InvalidArgument, "Error: Hash fields must be valid UTF-8"))
+}
+
+/// Looks up the hash stored at `key`, purging it first if it has expired.
+/// Replies WRONG_TYPE if the key holds another type.
+fn hash_mut<'a>(db: &'a mut Keyspace, db_instance: &DbInstance, key: &str) -> Result<Option<&'a mut Hash>, Reply> {
+    purge_if_expired(db, db_instance, key);
+    match db.get_mut(key).map(|entry| &mut entry.value) {
+        Some(Value::Hash(fields)) => Ok(Some(fields)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Runs a read-only command on the hash stored at `key` (`None` if the key
+/// doesn't exist).
+fn with_hash(key: &str, current_db_instance: &Option<Arc<DbInstance>>, read: impl FnOnce(Option<&Hash>) -> Reply) -> Reply {
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match hash_mut(&mut db, db_instance, key) {
+                Ok(fields) => read(fields.map(|fields| &*fields)),
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::testing::TestDb;
+    use crate::reply::{ErrorCode, Reply};
+
+    #[test]
+    fn fields_are_set_read_and_deleted() {
+        let db = TestDb::new("hash_test_fields");
+        assert_eq!(db.run("HSET(\"h\",\"a\",\"1\",\"b\",\"2\",\"a\",\"3\")"), "2");
+        assert_eq!(db.run("HSET(\"h\",\"b\",\"4\",\"c\",\"5\")"), "1");
+        assert_eq!(db.run("HGET(\"h\",\"a\")"), "3");
+        assert_eq!(db.run("HGET(\"h\",\"x\")"), "(nil)");
+        assert_eq!(db.run("HMGET(\"h\",\"c\",\"x\",\"b\")"), "5\n(nil)\n4");
+        assert_eq!(db.run("HGETALL(\"h\")"), "a\n3\nb\n4\nc\n5");
+        assert_eq!(db.run("HEXISTS(\"h\",\"c\")"), "1");
+        assert_eq!(db.run("HLEN(\"h\")"), "3");
+
+        assert_eq!(db.run("HINCRBY(\"h\",\"a\",\"-5\")"), "-2");
+        assert_eq!(db.run("HINCRBY(\"h\",\"n\",\"7\")"), "7");
+        assert_eq!(db.run("HINCRBY(\"h\",\"n\",\"x\")"), "Error: Increment must be an integer");
+        db.run("HSET(\"h\",\"s\",\"text\")");
+        assert_eq!(db.run("HINCRBY(\"h\",\"s\",\"1\")"), "Error: Hash value is not an integer");
+
+        assert_eq!(db.run("HDEL(\"h\",\"a\",\"a\",\"x\")"), "1");
+        assert_eq!(db.run("HDEL(\"h\",\"b\",\"c\",\"n\",\"s\")"), "4");
+        assert_eq!(db.run("EXISTS(\"h\")"), "0");
+        assert_eq!(db.run("HGETALL(\"h\")"), "");
+        db.run("SET(\"s\",\"v\")");
+        assert!(matches!(db.reply("HGET(\"s\",\"a\")"), Reply::Error(ErrorCode::WrongType, _)));
+    }
+
+    #[test]
+    fn field_names_must_be_utf8() {
+        let db = TestDb::new("hash_test_utf8");
+        db.run("HSET(\"h\",\"a\",\"1\")");
+        let invalid = vec![b"h".to_vec(), vec![0xff]];
+        for reply in [
+            super::get(invalid.clone(), &db.db),
+            super::exists(invalid.clone(), &db.db),
+            super::mget(invalid.clone(), &db.db),
+            super::del(invalid, &db.db),
+        ] {
+            assert!(matches!(reply, Reply::Error(ErrorCode::InvalidArgument, _)), "{:?}", reply);
+        }
+        assert_eq!(db.run("HLEN(\"h\")"), "1");
+    }
+
+    #[test]
+    fn changes_are_replayed_after_a_restart() {
+        let mut db = TestDb::new("hash_test_replay");
+        db.run("HSET(\"h\",\"a\",\"1\",\"b\",\"2\",\"c\",\"3\")");
+        db.run("HINCRBY(\"h\",\"a\",\"10\")");
+        db.run("HDEL(\"h\",\"b\")");
+        db.run("HSET(\"h\",\"d\",\"4\")");
+        db.run("HSET(\"gone\",\"x\",\"1\")");
+        db.run("HDEL(\"gone\",\"x\")");
+        let version = db.version("h");
+
+        db.reload();
+        assert_eq!(db.run("HGETALL(\"h\")"), "a\n11\nc\n3\nd\n4");
+        assert_eq!(db.version("h"), version);
+        assert_eq!(db.run("EXISTS(\"gone\")"), "0");
+    }
+}
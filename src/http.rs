@@ -0,0 +1,459 @@
+// =======================================================
+// 🧠 INFO: HTTP/JSON API
+// =======================================================
+// A small embedded HTTP/1.1 server for services that can only talk HTTP.
+// It shares the DbMap with the TCP listener.
+//
+// Endpoints:
+// - POST   /dbs                      create a database; body {"name": "...", "username": "...", "password": "..."}
+//                                    (username/password optional; giving them enables authentication)
+// - DELETE /dbs/{db}                 drop a database
+// - GET    /dbs/{db}/keys/{key}      read a key: {"key": "...", "value": "..."}
+// - PUT    /dbs/{db}/keys/{key}      write the request body as the value; optional ?ttl=10s
+// - DELETE /dbs/{db}/keys/{key}      delete a key
+//
+// Databases with authentication require HTTP Basic credentials matching the
+// database's username and password. Values that are not UTF-8 are returned as
+// {"base64": "..."}, like in JSON snapshots.
+
+use std::collections::HashSet;
+use std::io;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::db::{self, CreateDbError, DbInstance, DbMap};
+use crate::logger::log_info;
+use crate::parser;
+use crate::reply::{ErrorCode, Reply};
+
+/// Largest request body accepted (same limit as binary literals).
+const MAX_BODY_SIZE: usize = parser::MAX_LITERAL_SIZE;
+/// Most header lines accepted per request.
+const MAX_HEADERS: usize = 100;
+/// Longest request line or header line accepted.
+const MAX_LINE: usize = 8 * 1024;
+
+/// Credentials already verified on a connection, as (password hash, username,
+/// password), so keep-alive requests don't pay for bcrypt every time.
+type VerifiedCredentials = HashSet<(String, String, String)>;
+
+/// A parsed HTTP request.
+struct Request {
+    method: String,
+    // Decoded path segments, e.g. ["dbs", "users", "keys", "42"].
+    segments: Vec<String>,
+    // Decoded query parameters.
+    query: Vec<(String, String)>,
+    // Credentials from an `Authorization: Basic` header.
+    credentials: Option<(String, String)>,
+    // Whether the client asked to close the connection afterwards.
+    close: bool,
+    body: Vec<u8>,
+}
+
+/// An HTTP response with a JSON body.
+struct Response {
+    status: u16,
+    body: serde_json::Value,
+    // Ask the client for Basic credentials.
+    challenge: bool,
+}
+
+impl Response {
+    fn ok(body: serde_json::Value) -> Self {
+        Self { status: 200, body, challenge: false }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        let message = message.strip_prefix("Error: ").unwrap_or(message);
+        Self {
+            status,
+            body: json!({ "status": "error", "error": message }),
+            challenge: status == 401,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateDb {
+    name: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Serialize)]
+struct KeyValue {
+    key: String,
+    #[serde(with = "db::json_bytes")]
+    value: Vec<u8>,
+}
+
+/// Starts the HTTP listener in a background task.
+pub async fn start_server(address: String, db_map: DbMap) -> io::Result<()> {
+    let listener = TcpListener::bind(&address).await?;
+    log_info(&format!("HTTP API running on {}", address));
+
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = match listener.accept().await {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("Error accepting HTTP connection: {}", e);
+                    continue;
+                }
+            };
+            let db_map = db_map.clone();
+            tokio::spawn(handle_connection(socket, db_map));
+        }
+    });
+    Ok(())
+}
+
+/// Serves requests on one connection (keep-alive) until it is closed.
+async fn handle_connection(mut socket: TcpStream, db_map: DbMap) {
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+    let mut verified = VerifiedCredentials::new();
+
+    loop {
+        let (response, close) = match read_request(&mut reader).await {
+            Ok(Some(request)) => {
+                let close = request.close;
+                (route(request, &db_map, &mut verified).await, close)
+            }
+            Ok(None) => break, // Connection closed by client
+            Err(e) => (Response::error(400, &e.to_string()), true),
+        };
+
+        let body = response.body.to_string();
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            response.status,
+            reason(response.status),
+            body.len()
+        );
+        if response.challenge {
+            head.push_str("WWW-Authenticate: Basic realm=\"db-server\"\r\n");
+        }
+        if close {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+
+        if let Err(e) = writer.write_all((head + &body).as_bytes()).await {
+            eprintln!("Error writing to socket: {}", e);
+            break;
+        }
+        if close {
+            break;
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+fn bad_request(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads one line of the request head, up to `MAX_LINE` bytes. Returns
+/// `None` at end of stream.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if (&mut *reader).take(MAX_LINE as u64).read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.len() == MAX_LINE && !line.ends_with(b"\n") {
+        return Err(bad_request("Request line or header too long"));
+    }
+    String::from_utf8(line).map(Some).map_err(|_| bad_request("Request head is not valid UTF-8"))
+}
+
+/// Reads one request. Returns `None` if the connection closed between requests.
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Request>> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+
+    let mut parts = line.split_whitespace();
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) => (method.to_string(), target.to_string(), version.to_string()),
+        _ => return Err(bad_request("Malformed request line")),
+    };
+
+    let mut content_length = 0;
+    let mut credentials = None;
+    // HTTP/1.0 closes after each request unless asked otherwise
+    let mut close = version == "HTTP/1.0";
+    for _ in 0..=MAX_HEADERS {
+        let header = read_line(reader).await?.ok_or_else(|| bad_request("Unexpected end of headers"))?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        let (name, value) = header.split_once(':').ok_or_else(|| bad_request("Malformed header"))?;
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value.parse().map_err(|_| bad_request("Invalid Content-Length"))?;
+                if content_length > MAX_BODY_SIZE {
+                    return Err(bad_request("Request body too large"));
+                }
+            }
+            "transfer-encoding" => return Err(bad_request("Chunked request bodies are not supported")),
+            "authorization" => credentials = parse_basic_auth(value),
+            "connection" => close = value.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+
+    // The body grows as it arrives rather than being sized from the header
+    let mut body = Vec::new();
+    (&mut *reader).take(content_length as u64).read_to_end(&mut body).await?;
+    if body.len() < content_length {
+        return Err(bad_request("Unexpected end of request body"));
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect::<Result<_, _>>()?;
+    let query = query
+        .split('&')
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(name)?, percent_decode(value)?))
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(Some(Request { method, segments, query, credentials, close, body }))
+}
+
+/// Decodes %XX escapes (and '+' as space) in a URL component.
+fn percent_decode(s: &str) -> io::Result<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).ok_or_else(|| bad_request("Invalid percent-encoding"))?;
+                let hex = std::str::from_utf8(hex).map_err(|_| bad_request("Invalid percent-encoding"))?;
+                out.push(u8::from_str_radix(hex, 16).map_err(|_| bad_request("Invalid percent-encoding"))?);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| bad_request("URL is not valid UTF-8"))
+}
+
+/// Extracts username and password from a `Basic` authorization header value.
+fn parse_basic_auth(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Dispatches a request to its endpoint.
+async fn route(request: Request, db_map: &DbMap, verified: &mut VerifiedCredentials) -> Response {
+    let segments: Vec<&str> = request.segments.iter().map(String::as_str).collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["dbs"]) => create_db(&request, db_map),
+        ("DELETE", ["dbs", name]) => drop_db(name, &request, db_map, verified).await,
+        (method, ["dbs", name, "keys", key]) => {
+            let db_instance = match open_db(name, &request, db_map, verified).await {
+                Ok(db_instance) => db_instance,
+                Err(response) => return response,
+            };
+            key_request(method, key, &request, db_instance, db_map)
+        }
+        (_, ["dbs"]) | (_, ["dbs", _]) => Response::error(405, "Method not allowed"),
+        _ => Response::error(404, "No such endpoint"),
+    }
+}
+
+/// Loads a database and checks the request's credentials against it.
+async fn open_db(
+    name: &str,
+    request: &Request,
+    db_map: &DbMap,
+    verified: &mut VerifiedCredentials,
+) -> Result<DbInstance, Response> {
+    let db_instance = db::get_or_load(db_map, name)
+        .ok_or_else(|| Response::error(404, &format!("Database '{}' not found", name)))?;
+
+    if db_instance.require_auth {
+        let (username, password) = request
+            .credentials
+            .as_ref()
+            .ok_or_else(|| Response::error(401, "Authentication required"))?;
+        // Keyed by the stored hash, so a recreated database is checked afresh
+        let entry = (
+            db_instance.password.clone().unwrap_or_default(),
+            username.clone(),
+            password.clone(),
+        );
+        if verified.contains(&entry) {
+            return Ok(db_instance);
+        }
+        match db_instance.check_credentials(username, password).await {
+            Ok(true) => {
+                verified.insert(entry);
+            }
+            Ok(false) => return Err(Response::error(401, "Authentication failed")),
+            Err(e) => {
+                log_info(&format!("⚠️ Error verifying password for '{}': {}", name, e));
+                return Err(Response::error(500, "Authentication error"));
+            }
+        }
+    }
+    Ok(db_instance)
+}
+
+/// POST /dbs
+fn create_db(request: &Request, db_map: &DbMap) -> Response {
+    let body: CreateDb = match serde_json::from_slice(&request.body) {
+        Ok(body) => body,
+        Err(e) => return Response::error(400, &format!("Invalid request body: {}", e)),
+    };
+
+    let credentials = match (body.username, body.password) {
+        (Some(username), Some(password)) => Some((username, password)),
+        (None, None) => None,
+        _ => return Response::error(400, "Both username and password are required for authentication"),
+    };
+
+    match db::create_database(db_map, &body.name, credentials) {
+        Ok(_) => Response {
+            status: 201,
+            body: json!({ "status": "ok", "name": body.name }),
+            challenge: false,
+        },
+        Err(e @ CreateDbError::AlreadyExists(_)) => Response::error(409, &e.to_string()),
+        Err(e @ CreateDbError::InvalidName(_)) => Response::error(400, &e.to_string()),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// DELETE /dbs/{db}
+async fn drop_db(name: &str, request: &Request, db_map: &DbMap, verified: &mut VerifiedCredentials) -> Response {
+    if let Err(response) = open_db(name, request, db_map, verified).await {
+        return response;
+    }
+    match db::drop_database(db_map, name) {
+        Ok(()) => Response::ok(json!({ "status": "ok" })),
+        Err(e) => Response::error(500, &format!("Error deleting database file: {}", e)),
+    }
+}
+
+/// GET/PUT/DELETE /dbs/{db}/keys/{key}
+fn key_request(method: &str, key: &str, request: &Request, db_instance: DbInstance, db_map: &DbMap) -> Response {
+    let db_instance = Some(Arc::new(db_instance));
+    let key_arg = key.as_bytes().to_vec();
+
+    let reply = match method {
+        "GET" => parser::execute("GET", vec![key_arg], &db_instance, db_map),
+        "PUT" => {
+            let mut args = vec![key_arg, request.body.clone()];
+            if let Some((_, ttl)) = request.query.iter().find(|(name, _)| name == "ttl") {
+                // Anything but a duration would be read as a SET option (NX, GET, ...)
+                if let Err(e) = parser::parse_duration(ttl) {
+                    return Response::error(400, &e);
+                }
+                args.push(ttl.as_bytes().to_vec());
+            }
+            parser::execute("SET", args, &db_instance, db_map)
+        }
+        "DELETE" => parser::execute("DEL", vec![key_arg], &db_instance, db_map),
+        _ => return Response::error(405, "Method not allowed"),
+    };
+
+    match reply {
+        Reply::Ok => Response::ok(json!({ "status": "ok" })),
+        Reply::Value(value) => Response::ok(json!(KeyValue { key: key.to_string(), value })),
+        Reply::Error(ErrorCode::KeyNotFound, message) => Response::error(404, &message),
+        Reply::Error(ErrorCode::Syntax | ErrorCode::UnknownCommand | ErrorCode::InvalidArgument | ErrorCode::WrongType, message) => {
+            Response::error(400, &message)
+        }
+        Reply::Error(_, message) => Response::error(500, &message),
+        other => Response::ok(json!({ "status": "ok", "value": String::from_utf8_lossy(&other.to_text()) })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut input: &[u8]) -> io::Result<Option<Request>> {
+        read_request(&mut input).await
+    }
+
+    #[tokio::test]
+    async fn reads_requests() {
+        let request = read(b"PUT /dbs/a/keys/k%20x?ttl=10s HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.segments, ["dbs", "a", "keys", "k x"]);
+        assert_eq!(request.query, [("ttl".to_string(), "10s".to_string())]);
+        assert_eq!(request.body, b"hello");
+        assert!(!request.close);
+
+        assert!(read(b"").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_and_truncated_requests() {
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        let error = read(long_target.as_bytes()).await.err().unwrap();
+        assert_eq!(error.to_string(), "Request line or header too long");
+
+        let long_header = format!("GET / HTTP/1.1\r\nX-Pad: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        let error = read(long_header.as_bytes()).await.err().unwrap();
+        assert_eq!(error.to_string(), "Request line or header too long");
+
+        // An announced body that never arrives isn't allocated up front
+        let short_body = format!("PUT / HTTP/1.1\r\nContent-Length: {}\r\n\r\nabc", MAX_BODY_SIZE);
+        let error = read(short_body.as_bytes()).await.err().unwrap();
+        assert_eq!(error.to_string(), "Unexpected end of request body");
+
+        let too_large = format!("PUT / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        let error = read(too_large.as_bytes()).await.err().unwrap();
+        assert_eq!(error.to_string(), "Request body too large");
+    }
+}
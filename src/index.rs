@@ -0,0 +1,495 @@
+// =======================================================
+// 🧠 INFO: Secondary Indexes
+// =======================================================
+// A secondary index maps the value of one field of the hashes and JSON
+// documents in a database to the keys holding it, so keys can be looked up
+// by value (IDX.FIND) or by a range of values (IDX.RANGE).
+//
+// Indexes are declared on a path (see json.rs): `$.a.b` selects a node of
+// JSON documents, and a single member such as `$.email` also selects the
+// `email` field of hashes. Only scalars are indexed. Strings that parse as
+// numbers are indexed as numbers, so the hash field "30" and the JSON number
+// 30 are equal; numbers sort before all other strings.
+//
+// Index contents are updated from each write-ahead log record as it is
+// logged (or, for records that only carry a change, from the value it
+// produced), so every command that writes maintains them, and are rebuilt
+// when a database is loaded. Only the definitions are stored in snapshots.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::db::{DbInstance, Keyspace, SerializableValue, Value};
+use crate::json::{self, Segment};
+use crate::logger::log_info;
+use crate::parser::{key_arg, no_database, parse_float, parse_integer, usage};
+use crate::reply::{ErrorCode, Reply};
+use crate::wal::LogRecord;
+use crate::zset::Score;
+
+/// A secondary index as declared by IDX.CREATE.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub path: String,
+}
+
+/// An indexed field value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum IndexValue {
+    Number(Score),
+    Text(String),
+}
+
+impl IndexValue {
+    /// Indexes text as a number if it parses as one.
+    fn from_text(text: &str) -> Self {
+        match parse_float(text.as_bytes()) {
+            Some(number) => Self::Number(Score::new(number)),
+            None => Self::Text(text.to_string()),
+        }
+    }
+
+    fn from_json(node: &JsonValue) -> Option<Self> {
+        match node {
+            JsonValue::Number(number) => number.as_f64().map(|number| Self::Number(Score::new(number))),
+            JsonValue::String(text) => Some(Self::from_text(text)),
+            JsonValue::Bool(flag) => Some(Self::Text(flag.to_string())),
+            _ => None,
+        }
+    }
+
+    fn from_hash_field(value: &[u8]) -> Option<Self> {
+        std::str::from_utf8(value).ok().map(Self::from_text)
+    }
+}
+
+#[derive(Debug)]
+struct Index {
+    definition: IndexDefinition,
+    path: Vec<Segment>,
+    // Indexed values with the keys holding them, in value order.
+    entries: BTreeSet<(IndexValue, String)>,
+    // The indexed value of each key, to find its entry when it changes.
+    by_key: HashMap<String, IndexValue>,
+}
+
+impl Index {
+    fn new(definition: IndexDefinition, data: &Keyspace) -> Option<Self> {
+        let path = json::parse_path(&definition.path)?;
+        let mut index = Self { definition, path, entries: BTreeSet::new(), by_key: HashMap::new() };
+        for (key, entry) in data {
+            let value = index.value_of(&entry.value);
+            index.update(key, value);
+        }
+        Some(index)
+    }
+
+    /// The hash field the path selects, if it is a single member.
+    fn hash_field(&self) -> Option<&str> {
+        match self.path.as_slice() {
+            [Segment::Key(field)] => Some(field),
+            _ => None,
+        }
+    }
+
+    fn value_of(&self, value: &Value) -> Option<IndexValue> {
+        match value {
+            Value::Json(doc) => IndexValue::from_json(json::lookup(doc, &self.path)?),
+            Value::Hash(fields) => IndexValue::from_hash_field(fields.get(self.hash_field()?)?),
+            _ => None,
+        }
+    }
+
+    fn serialized_value_of(&self, value: &SerializableValue) -> Option<IndexValue> {
+        match value {
+            SerializableValue::Json(doc) => IndexValue::from_json(json::lookup(doc, &self.path)?),
+            SerializableValue::Hash(fields) => IndexValue::from_hash_field(&fields.get(self.hash_field()?)?.0),
+            _ => None,
+        }
+    }
+
+    /// Records that `key` now holds `value` (`None` if it isn't indexed).
+    fn update(&mut self, key: &str, value: Option<IndexValue>) {
+        if self.by_key.get(key) == value.as_ref() {
+            return;
+        }
+        if let Some(old) = self.by_key.remove(key) {
+            self.entries.remove(&(old, key.to_string()));
+        }
+        if let Some(value) = value {
+            self.entries.insert((value.clone(), key.to_string()));
+            self.by_key.insert(key.to_string(), value);
+        }
+    }
+
+    /// Keys whose value is between `min` and `max`, in value order.
+    fn range(&self, min: Bound<IndexValue>, max: Bound<IndexValue>) -> impl Iterator<Item = &str> {
+        let start = match &min {
+            Bound::Included(min) | Bound::Excluded(min) => Bound::Included((min.clone(), String::new())),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.entries
+            .range((start, Bound::Unbounded))
+            .skip_while(move |(value, _)| matches!(&min, Bound::Excluded(min) if value <= min))
+            .take_while(move |(value, _)| match &max {
+                Bound::Included(max) => value <= max,
+                Bound::Excluded(max) => value < max,
+                Bound::Unbounded => true,
+            })
+            .map(|(_, key)| key.as_str())
+    }
+}
+
+/// The secondary indexes of a database, by name.
+#[derive(Debug, Default)]
+pub struct Indexes {
+    indexes: BTreeMap<String, Index>,
+}
+
+impl Indexes {
+    /// Builds the indexes of a loaded database from their definitions.
+    pub fn new(definitions: Vec<IndexDefinition>, data: &Keyspace) -> Self {
+        let mut indexes = BTreeMap::new();
+        for definition in definitions {
+            let name = definition.name.clone();
+            match Index::new(definition, data) {
+                Some(index) => {
+                    indexes.insert(name, index);
+                }
+                None => log_info(&format!("⚠️ Skipping index '{}' with an invalid path", name)),
+            }
+        }
+        Self { indexes }
+    }
+
+    /// Definitions of every index, for snapshots.
+    pub fn definitions(&self) -> Vec<IndexDefinition> {
+        self.indexes.values().map(|index| index.definition.clone()).collect()
+    }
+
+    /// Updates the indexes for `key` now holding `value`.
+    pub fn update(&mut self, key: &str, value: &Value) {
+        for index in self.indexes.values_mut() {
+            let indexed = index.value_of(value);
+            index.update(key, indexed);
+        }
+    }
+
+    /// Updates the indexes for a mutation that is being logged.
+    pub fn apply(&mut self, record: &LogRecord) {
+        match record {
+            LogRecord::Set { key, entry } => {
+                for index in self.indexes.values_mut() {
+                    let value = index.serialized_value_of(&entry.value);
+                    index.update(key, value);
+                }
+            }
+            // Indexed from the resulting value by `DbInstance::log_change`
+            LogRecord::Update { .. } => {}
+            LogRecord::Del { key } | LogRecord::Expire { key } => {
+                for index in self.indexes.values_mut() {
+                    index.update(key, None);
+                }
+            }
+            LogRecord::Clear => {
+                for index in self.indexes.values_mut() {
+                    index.entries.clear();
+                    index.by_key.clear();
+                }
+            }
+            LogRecord::Batch { records } => {
+                for record in records {
+                    self.apply(record);
+                }
+            }
+        }
+    }
+}
+
+/// IDX.CREATE("name","path") - indexes the field at `path` of every hash and
+/// JSON document in the database
+pub fn create(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("IDX.CREATE(\"name\",\"path\")");
+    }
+    let mut args = args.into_iter();
+    let name = match index_name(args.next().unwrap()) {
+        Ok(name) => name,
+        Err(e) => return e,
+    };
+    let path = String::from_utf8_lossy(&args.next().unwrap()).into_owned();
+
+    match current_db_instance {
+        Some(db_instance) => {
+            {
+                let db = db_instance.data.lock().unwrap();
+                let mut indexes = db_instance.indexes.lock().unwrap();
+                if indexes.indexes.contains_key(&name) {
+                    return Reply::error(ErrorCode::InvalidArgument, format!("Error: Index '{}' already exists", name));
+                }
+                let definition = IndexDefinition { name: name.clone(), path };
+                let Some(index) = Index::new(definition, &db) else {
+                    return Reply::error(ErrorCode::InvalidArgument, "Error: Invalid JSON path");
+                };
+                indexes.indexes.insert(name.clone(), index);
+            }
+            // Definitions change rarely, so they are saved with a snapshot
+            // instead of being logged. Undo the change if that fails, or it
+            // would be lost on restart.
+            if let Err(e) = db_instance.save_to_file() {
+                db_instance.indexes.lock().unwrap().indexes.remove(&name);
+                return save_failed(db_instance, e);
+            }
+            Reply::Ok
+        }
+        None => no_database(),
+    }
+}
+
+/// IDX.DROP("name") - deletes an index
+pub fn drop_index(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("IDX.DROP(\"name\")");
+    }
+    let name = String::from_utf8_lossy(&args[0]).into_owned();
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let Some(index) = db_instance.indexes.lock().unwrap().indexes.remove(&name) else {
+                return index_not_found(&name);
+            };
+            if let Err(e) = db_instance.save_to_file() {
+                // Rebuild the index, as writes since it was removed skipped it
+                let db = db_instance.data.lock().unwrap();
+                if let Some(index) = Index::new(index.definition, &db) {
+                    db_instance.indexes.lock().unwrap().indexes.insert(name, index);
+                }
+                return save_failed(db_instance, e);
+            }
+            Reply::Ok
+        }
+        None => no_database(),
+    }
+}
+
+/// IDX.LIST() - replies with the name and path of every index
+pub fn list(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if !args.is_empty() {
+        return usage("IDX.LIST()");
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let indexes = db_instance.indexes.lock().unwrap();
+            Reply::Map(
+                indexes
+                    .definitions()
+                    .into_iter()
+                    .map(|definition| {
+                        (Reply::Value(definition.name.into_bytes()), Reply::Value(definition.path.into_bytes()))
+                    })
+                    .collect(),
+            )
+        }
+        None => no_database(),
+    }
+}
+
+/// IDX.FIND("name","value") - replies with the keys whose indexed field
+/// equals `value`, in key order
+pub fn find(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("IDX.FIND(\"name\",\"value\")");
+    }
+    let mut args = args.into_iter();
+    let name = String::from_utf8_lossy(&args.next().unwrap()).into_owned();
+    let value = IndexValue::from_text(&String::from_utf8_lossy(&args.next().unwrap()));
+
+    query(&name, Bound::Included(value.clone()), Bound::Included(value), 0, None, current_db_instance)
+}
+
+/// IDX.RANGE("name","min","max",[LIMIT,"offset","count"]) - replies with the
+/// keys whose indexed field is between `min` and `max`, in value order.
+/// Bounds are inclusive unless prefixed with "(", and may be "-inf" or
+/// "+inf" (which covers all numbers but no other strings)
+pub fn range(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "IDX.RANGE(\"name\",\"min\",\"max\",[LIMIT,\"offset\",\"count\"])";
+    if args.len() != 3 && args.len() != 6 {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let name = String::from_utf8_lossy(&args.next().unwrap()).into_owned();
+    let min = parse_bound(&args.next().unwrap());
+    let max = parse_bound(&args.next().unwrap());
+
+    let (offset, count) = match (args.next(), args.next(), args.next()) {
+        (Some(option), Some(offset), Some(count)) if option.eq_ignore_ascii_case(b"LIMIT") => {
+            let Some(offset) = parse_integer(&offset).and_then(|n| usize::try_from(n).ok()) else {
+                return Reply::error(ErrorCode::InvalidArgument, "Error: Offset must be a non-negative integer");
+            };
+            let Some(count) = parse_integer(&count) else {
+                return Reply::error(ErrorCode::InvalidArgument, "Error: Count must be an integer");
+            };
+            // A negative count returns every key after the offset
+            (offset, usize::try_from(count).ok())
+        }
+        (None, None, None) => (0, None),
+        _ => return usage(USAGE),
+    };
+
+    query(&name, min, max, offset, count, current_db_instance)
+}
+
+/// Replies with the live keys of index `name` whose value is between `min`
+/// and `max`, skipping `offset` of them and returning at most `count`.
+fn query(
+    name: &str,
+    min: Bound<IndexValue>,
+    max: Bound<IndexValue>,
+    offset: usize,
+    count: Option<usize>,
+    current_db_instance: &Option<Arc<DbInstance>>,
+) -> Reply {
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            let indexes = db_instance.indexes.lock().unwrap();
+            let Some(index) = indexes.indexes.get(name) else {
+                return index_not_found(name);
+            };
+            let keys = index
+                .range(min, max)
+                // Keys that expired but weren't purged yet are still indexed
+                .filter(|key| db.get(*key).is_some_and(|entry| !entry.is_expired()))
+                .skip(offset)
+                .take(count.unwrap_or(usize::MAX));
+            Reply::Array(keys.map(|key| Reply::Value(key.as_bytes().to_vec())).collect())
+        }
+        None => no_database(),
+    }
+}
+
+/// Parses an IDX.RANGE bound: a value, "-inf"/"+inf", or "(" followed by a
+/// value for an exclusive bound.
+fn parse_bound(arg: &[u8]) -> Bound<IndexValue> {
+    let (exclusive, value) = match arg {
+        [b'(', value @ ..] => (true, value),
+        value => (false, value),
+    };
+    let value = match value {
+        b"-inf" => IndexValue::Number(Score::new(f64::NEG_INFINITY)),
+        b"+inf" | b"inf" => IndexValue::Number(Score::new(f64::INFINITY)),
+        value => IndexValue::from_text(&String::from_utf8_lossy(value)),
+    };
+    if exclusive { Bound::Excluded(value) } else { Bound::Included(value) }
+}
+
+/// Converts an index name argument; like keys, names must be valid UTF-8.
+fn index_name(arg: Vec<u8>) -> Result<String, Reply> {
+    key_arg(arg).map_err(|_| Reply::error(ErrorCode::InvalidArgument, "Error: Index names must be valid UTF-8"))
+}
+
+fn index_not_found(name: &str) -> Reply {
+    Reply::error(ErrorCode::InvalidArgument, format!("Error: Index '{}' not found", name))
+}
+
+/// Reply for an index change that was undone because the snapshot holding
+/// the definitions couldn't be saved.
+fn save_failed(db_instance: &DbInstance, e: std::io::Error) -> Reply {
+    log_info(&format!("⚠️ Failed to save indexes of '{}': {}", db_instance.name, e));
+    Reply::error(ErrorCode::Internal, format!("Error: Failed to save index: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::testing::TestDb;
+    use crate::reply::{ErrorCode, Reply};
+
+    #[test]
+    fn indexes_find_hash_fields_and_json_nodes() {
+        let db = TestDb::new("index_test_find");
+        db.run("HSET(\"u:1\",\"email\",\"ann@example.com\",\"age\",\"30\")");
+        db.run(r#"JSON.SET("u:2","$","{\"email\":\"bob@example.com\",\"age\":25,\"tags\":[\"a\"]}")"#);
+        db.run("HSET(\"u:3\",\"age\",\"30\")");
+        db.run("SET(\"u:4\",\"not indexed\")");
+        assert_eq!(db.run("IDX.CREATE(\"by_email\",\"$.email\")"), "OK");
+        assert_eq!(db.run("IDX.CREATE(\"by_age\",\"age\")"), "OK");
+        assert_eq!(db.run("IDX.CREATE(\"by_tag\",\"$.tags[0]\")"), "OK");
+        assert_eq!(db.run("IDX.LIST()"), "by_age\nage\nby_email\n$.email\nby_tag\n$.tags[0]");
+
+        assert_eq!(db.run("IDX.FIND(\"by_email\",\"bob@example.com\")"), "u:2");
+        assert_eq!(db.run("IDX.FIND(\"by_age\",\"30\")"), "u:1\nu:3");
+        assert_eq!(db.run("IDX.FIND(\"by_age\",\"30.0\")"), "u:1\nu:3");
+        assert_eq!(db.run("IDX.FIND(\"by_tag\",\"a\")"), "u:2");
+        assert_eq!(db.run("IDX.FIND(\"by_age\",\"31\")"), "");
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"-inf\",\"+inf\")"), "u:2\nu:1\nu:3");
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"(25\",\"30\")"), "u:1\nu:3");
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"0\",\"100\",\"LIMIT\",\"1\",\"1\")"), "u:1");
+        assert_eq!(db.run("IDX.RANGE(\"by_email\",\"a\",\"b\")"), "u:1");
+
+        assert_eq!(db.run("IDX.CREATE(\"by_age\",\"$.x\")"), "Error: Index 'by_age' already exists");
+        assert_eq!(db.run("IDX.CREATE(\"bad\",\"$..x\")"), "Error: Invalid JSON path");
+        assert_eq!(db.run("IDX.FIND(\"missing\",\"1\")"), "Error: Index 'missing' not found");
+        assert_eq!(db.run("IDX.DROP(\"by_tag\")"), "OK");
+        assert_eq!(db.run("IDX.DROP(\"by_tag\")"), "Error: Index 'by_tag' not found");
+    }
+
+    #[test]
+    fn writes_keep_indexes_up_to_date() {
+        let db = TestDb::new("index_test_updates");
+        db.run("IDX.CREATE(\"by_age\",\"age\")");
+        db.run("HSET(\"a\",\"age\",\"1\")");
+        db.run("HSET(\"b\",\"age\",\"2\")");
+        db.run(r#"JSON.SET("c","$","{\"age\":3}")"#);
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"-inf\",\"+inf\")"), "a\nb\nc");
+
+        db.run("HINCRBY(\"a\",\"age\",\"5\")");
+        db.run(r#"JSON.NUMINCRBY("c","$.age","-3")"#);
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"-inf\",\"+inf\")"), "c\nb\na");
+        db.run("SET(\"b\",\"plain\")");
+        db.run("RENAME(\"a\",\"z\")");
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"-inf\",\"+inf\")"), "c\nz");
+        db.run("HDEL(\"z\",\"age\")");
+        db.run("DEL(\"c\")");
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"-inf\",\"+inf\")"), "");
+    }
+
+    #[test]
+    fn indexes_are_rebuilt_when_loaded() {
+        let mut db = TestDb::new("index_test_reload");
+        db.run("HSET(\"a\",\"age\",\"1\")");
+        db.run("IDX.CREATE(\"by_age\",\"age\")");
+        db.run("IDX.CREATE(\"dropped\",\"age\")");
+        db.run("IDX.DROP(\"dropped\")");
+        // Logged after the definitions were saved
+        db.run("HSET(\"b\",\"age\",\"2\")");
+        db.run("HSET(\"a\",\"age\",\"3\")");
+
+        db.reload();
+        assert_eq!(db.run("IDX.LIST()"), "by_age\nage");
+        assert_eq!(db.run("IDX.RANGE(\"by_age\",\"-inf\",\"+inf\")"), "b\na");
+    }
+
+    #[test]
+    fn changes_that_cannot_be_saved_are_undone() {
+        let mut db = TestDb::new("index_test_save_failure");
+        db.run("IDX.CREATE(\"kept\",\"age\")");
+        db.break_snapshots();
+        let reply = db.reply("IDX.CREATE(\"new\",\"age\")");
+        assert!(matches!(reply, Reply::Error(ErrorCode::Internal, _)), "{:?}", reply);
+        // A dropped index is rebuilt from the data when it is restored
+        db.run("HSET(\"a\",\"age\",\"1\")");
+        let reply = db.reply("IDX.DROP(\"kept\")");
+        assert!(matches!(reply, Reply::Error(ErrorCode::Internal, _)), "{:?}", reply);
+        assert_eq!(db.run("IDX.LIST()"), "kept\nage");
+        assert_eq!(db.run("IDX.FIND(\"kept\",\"1\")"), "a");
+
+        db.repair_snapshots();
+        db.reload();
+        assert_eq!(db.run("IDX.LIST()"), "kept\nage");
+    }
+}
@@ -0,0 +1,442 @@
+// =======================================================
+// 🧠 INFO: JSON Document Commands
+// =======================================================
+// JSON documents are stored as `Value::Json` and updated in place, so a
+// client can change one field without sending the whole document back.
+// Updates are logged the same way, as the path and what changed there.
+//
+// Paths select a node inside a document:
+// - `$` (or `.`) is the whole document
+// - `.name` selects an object member, `["name"]` one with any characters
+// - `[n]` selects an array element; negative indexes count from the end
+// e.g. `$.users[0].name`. The leading `$` may be left out, and then also the
+// first dot: `users[0].name`.
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value as JsonValue};
+
+use crate::db::{DbInstance, Value, ValueWithExpiry};
+use crate::parser::{key_arg, key_not_found, no_database, purge_if_expired, usage, wrong_type};
+use crate::reply::{ErrorCode, Reply};
+use crate::wal::{Change, LogRecord};
+
+/// One step of a path. Logged as a bare string or number.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Segment {
+    Key(String),
+    Index(i64),
+}
+
+/// JSON.SET("key","path","json") - stores a document at the root path, or
+/// replaces the node at `path` inside an existing document (adding an object
+/// member if needed). The TTL of an existing document is kept
+pub fn set(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 3 {
+        return usage("JSON.SET(\"key\",\"path\",\"json\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let path = match path_arg(&args.next().unwrap()) {
+        Ok(path) => path,
+        Err(e) => return e,
+    };
+    let new = match json_arg(&args.next().unwrap()) {
+        Ok(new) => new,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            match db.get_mut(&key) {
+                Some(entry) => {
+                    let Value::Json(doc) = &mut entry.value else {
+                        return wrong_type();
+                    };
+                    if let Err(e) = set_at(doc, &path, new.clone()) {
+                        return e;
+                    }
+                    db_instance.log_change(&key, entry, Change::JsonSet { path, value: new });
+                }
+                None if path.is_empty() => {
+                    let mut entry = ValueWithExpiry::new(Value::Json(new), None);
+                    db_instance.log_set(&key, &mut entry);
+                    db.insert(key, entry);
+                }
+                None => {
+                    return Reply::error(ErrorCode::InvalidArgument, "Error: New documents must be set at the root path");
+                }
+            }
+            Reply::Ok
+        }
+        None => no_database(),
+    }
+}
+
+/// JSON.GET("key",["path"]) - replies with the node at `path` (the whole
+/// document by default) as JSON text, or nil if the key or path doesn't exist
+pub fn get(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() || args.len() > 2 {
+        return usage("JSON.GET(\"key\",[\"path\"])");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let path = match args.next().map(|path| path_arg(&path)).transpose() {
+        Ok(path) => path.unwrap_or_default(),
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            match db.get(&key).map(|entry| &entry.value) {
+                Some(Value::Json(doc)) => lookup(doc, &path).map_or(Reply::Nil, json_reply),
+                Some(_) => wrong_type(),
+                None => Reply::Nil,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// JSON.DEL("key",["path"]) - deletes the node at `path`, or the whole key
+/// for the root path (the default); replies with the number of nodes deleted
+pub fn del(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() || args.len() > 2 {
+        return usage("JSON.DEL(\"key\",[\"path\"])");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let path = match args.next().map(|path| path_arg(&path)).transpose() {
+        Ok(path) => path.unwrap_or_default(),
+        Err(e) => return e,
+    };
+
+    if !path.is_empty() {
+        return update_document(key, current_db_instance, |doc| match delete_at(doc, &path) {
+            true => Ok((Reply::Int(1), Change::JsonDel { path })),
+            // Nothing changed, so there is nothing to log
+            false => Err(Reply::Int(0)),
+        });
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            match db.get(&key).map(|entry| &entry.value) {
+                Some(Value::Json(_)) => {
+                    db.remove(&key);
+                    db_instance.log(LogRecord::Del { key });
+                    Reply::Int(1)
+                }
+                Some(_) => wrong_type(),
+                None => Reply::Int(0),
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// JSON.NUMINCRBY("key","path","n") - adds `n` to the number at `path` and
+/// replies with the result. Integers stay integers unless the sum overflows
+pub fn num_incr_by(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 3 {
+        return usage("JSON.NUMINCRBY(\"key\",\"path\",\"n\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let path = match path_arg(&args.next().unwrap()) {
+        Ok(path) => path,
+        Err(e) => return e,
+    };
+    let Ok(JsonValue::Number(delta)) = serde_json::from_slice(&args.next().unwrap()) else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Increment must be a number");
+    };
+
+    update_document(key, current_db_instance, |doc| {
+        let Some(target) = lookup_mut(doc, &path) else {
+            return Err(path_not_found());
+        };
+        let JsonValue::Number(current) = target else {
+            return Err(Reply::error(ErrorCode::InvalidArgument, "Error: Value at path is not a number"));
+        };
+        let Some(sum) = add_numbers(current, &delta) else {
+            return Err(Reply::error(ErrorCode::InvalidArgument, "Error: Increment would overflow"));
+        };
+        let reply = Reply::Value(sum.to_string().into_bytes());
+        *target = JsonValue::Number(sum.clone());
+        // Log the result rather than the increment, like HINCRBY
+        Ok((reply, Change::JsonSet { path, value: JsonValue::Number(sum) }))
+    })
+}
+
+/// JSON.ARRAPPEND("key","path","json",...) - appends values to the array at
+/// `path` and replies with its new length
+pub fn arr_append(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 3 {
+        return usage("JSON.ARRAPPEND(\"key\",\"path\",\"json\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let path = match path_arg(&args.next().unwrap()) {
+        Ok(path) => path,
+        Err(e) => return e,
+    };
+    let values = match args.map(|value| json_arg(&value)).collect::<Result<Vec<_>, _>>() {
+        Ok(values) => values,
+        Err(e) => return e,
+    };
+
+    update_document(key, current_db_instance, |doc| {
+        match lookup_mut(doc, &path) {
+            Some(JsonValue::Array(items)) => {
+                items.extend(values.iter().cloned());
+                Ok((Reply::Int(items.len() as i64), Change::JsonAppend { path, values }))
+            }
+            Some(_) => Err(Reply::error(ErrorCode::InvalidArgument, "Error: Value at path is not an array")),
+            None => Err(path_not_found()),
+        }
+    })
+}
+
+/// Applies `update` to the document stored at `key` and logs the change.
+/// `update` returns the reply to send and the change it made, or an error
+/// reply to leave the document untouched.
+fn update_document(
+    key: String,
+    current_db_instance: &Option<Arc<DbInstance>>,
+    update: impl FnOnce(&mut JsonValue) -> Result<(Reply, Change), Reply>,
+) -> Reply {
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let Some(entry) = db.get_mut(&key) else {
+                return key_not_found(&key);
+            };
+            let Value::Json(doc) = &mut entry.value else {
+                return wrong_type();
+            };
+            match update(doc) {
+                Ok((reply, change)) => {
+                    db_instance.log_change(&key, entry, change);
+                    reply
+                }
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// Parses a path (see the top of this file); the root path is empty.
+pub fn parse_path(path: &str) -> Option<Vec<Segment>> {
+    let mut rest = match path.strip_prefix('$') {
+        Some(rest) => rest,
+        None if path == "." => "",
+        None if path.starts_with(['.', '[']) => path,
+        None => return parse_path(&format!("$.{}", path)),
+    };
+
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return None;
+            }
+            segments.push(Segment::Key(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            if let Some(quote) = after.chars().next().filter(|&c| c == '"' || c == '\'') {
+                let quoted = &after[1..];
+                let close = quoted.find(quote)?;
+                segments.push(Segment::Key(quoted[..close].to_string()));
+                rest = quoted[close + 1..].strip_prefix(']')?;
+            } else {
+                let close = after.find(']')?;
+                segments.push(Segment::Index(after[..close].trim().parse().ok()?));
+                rest = &after[close + 1..];
+            }
+        } else {
+            return None;
+        }
+    }
+    Some(segments)
+}
+
+/// Finds the node at `path` in `doc`.
+pub fn lookup<'a>(doc: &'a JsonValue, path: &[Segment]) -> Option<&'a JsonValue> {
+    path.iter().try_fold(doc, |node, segment| match (node, segment) {
+        (JsonValue::Object(members), Segment::Key(name)) => members.get(name),
+        (JsonValue::Array(items), Segment::Index(index)) => items.get(resolve_index(items.len(), *index)?),
+        _ => None,
+    })
+}
+
+pub fn lookup_mut<'a>(doc: &'a mut JsonValue, path: &[Segment]) -> Option<&'a mut JsonValue> {
+    path.iter().try_fold(doc, |node, segment| match (node, segment) {
+        (JsonValue::Object(members), Segment::Key(name)) => members.get_mut(name),
+        (JsonValue::Array(items), Segment::Index(index)) => {
+            let index = resolve_index(items.len(), *index)?;
+            items.get_mut(index)
+        }
+        _ => None,
+    })
+}
+
+/// Replaces the node at `path` with `new`; the last segment may name a new
+/// object member, but arrays only grow through JSON.ARRAPPEND.
+pub fn set_at(doc: &mut JsonValue, path: &[Segment], new: JsonValue) -> Result<(), Reply> {
+    let Some((last, parent_path)) = path.split_last() else {
+        *doc = new;
+        return Ok(());
+    };
+    match (lookup_mut(doc, parent_path), last) {
+        (Some(JsonValue::Object(members)), Segment::Key(name)) => {
+            members.insert(name.clone(), new);
+            Ok(())
+        }
+        (Some(JsonValue::Array(items)), Segment::Index(index)) => {
+            let index = resolve_index(items.len(), *index).ok_or_else(path_not_found)?;
+            items[index] = new;
+            Ok(())
+        }
+        _ => Err(path_not_found()),
+    }
+}
+
+/// Removes the node at a non-root `path`, returning whether it existed.
+pub fn delete_at(doc: &mut JsonValue, path: &[Segment]) -> bool {
+    let Some((last, parent_path)) = path.split_last() else {
+        return false;
+    };
+    match (lookup_mut(doc, parent_path), last) {
+        // Keep the order of the remaining members
+        (Some(JsonValue::Object(members)), Segment::Key(name)) => members.shift_remove(name).is_some(),
+        (Some(JsonValue::Array(items)), Segment::Index(index)) => match resolve_index(items.len(), *index) {
+            Some(index) => {
+                items.remove(index);
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Resolves a possibly negative array index, or `None` if it is out of range.
+fn resolve_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    usize::try_from(index).ok().filter(|&index| index < len)
+}
+
+/// Adds two JSON numbers, keeping integers exact. `None` if the result
+/// isn't finite.
+fn add_numbers(a: &Number, b: &Number) -> Option<Number> {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64())
+        && let Some(sum) = a.checked_add(b)
+    {
+        return Some(sum.into());
+    }
+    Number::from_f64(a.as_f64()? + b.as_f64()?)
+}
+
+/// Reply carrying a node as compact JSON text.
+pub fn json_reply(node: &JsonValue) -> Reply {
+    Reply::Value(node.to_string().into_bytes())
+}
+
+fn path_arg(arg: &[u8]) -> Result<Vec<Segment>, Reply> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(parse_path)
+        .ok_or_else(|| Reply::error(ErrorCode::InvalidArgument, "Error: Invalid JSON path"))
+}
+
+fn json_arg(arg: &[u8]) -> Result<JsonValue, Reply> {
+    serde_json::from_slice(arg).map_err(|e| Reply::error(ErrorCode::InvalidArgument, format!("Error: Invalid JSON: {}", e)))
+}
+
+fn path_not_found() -> Reply {
+    Reply::error(ErrorCode::InvalidArgument, "Error: Path does not exist")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::testing::TestDb;
+
+    #[test]
+    fn nodes_are_set_read_and_deleted_by_path() {
+        let db = TestDb::new("json_test_paths");
+        assert_eq!(db.run(r#"JSON.SET("j","$","{\"users\":[{\"name\":\"ann\"},{\"name\":\"bob\"}],\"n\":1}")"#), "OK");
+        assert_eq!(db.run(r#"JSON.GET("j","$.users[0].name")"#), r#""ann""#);
+        assert_eq!(db.run(r#"JSON.GET("j","users[-1][\"name\"]")"#), r#""bob""#);
+        assert_eq!(db.run(r#"JSON.SET("j","$.users[1].age","30")"#), "OK");
+        assert_eq!(db.run(r#"JSON.SET("j","$.n","[1]")"#), "OK");
+        assert_eq!(db.run(r#"JSON.ARRAPPEND("j","$.n","2","\"x\"")"#), "3");
+        assert_eq!(db.run(r#"JSON.NUMINCRBY("j","$.n[0]","1.5")"#), "2.5");
+        assert_eq!(db.run(r#"JSON.DEL("j","$.users[0]")"#), "1");
+        assert_eq!(db.run(r#"JSON.DEL("j","$.users[5]")"#), "0");
+        assert_eq!(db.run(r#"JSON.GET("j")"#), r#"{"users":[{"name":"bob","age":30}],"n":[2.5,2,"x"]}"#);
+        assert_eq!(db.run(r#"JSON.DEL("j")"#), "1");
+        assert_eq!(db.run(r#"JSON.GET("j")"#), "(nil)");
+    }
+
+    #[test]
+    fn bad_paths_leave_the_document_untouched() {
+        let db = TestDb::new("json_test_errors");
+        db.run(r#"JSON.SET("j","$","{\"a\":{\"b\":1},\"s\":\"x\",\"l\":[]}")"#);
+        let not_found = "Error: Path does not exist";
+        assert_eq!(db.run(r#"JSON.GET("j","$.a.c")"#), "(nil)");
+        assert_eq!(db.run(r#"JSON.SET("j","$.a.c.d","1")"#), not_found);
+        assert_eq!(db.run(r#"JSON.SET("j","$.l[0]","1")"#), not_found);
+        assert_eq!(db.run(r#"JSON.NUMINCRBY("j","$.x","1")"#), not_found);
+        assert_eq!(db.run(r#"JSON.ARRAPPEND("j","$.x","1")"#), not_found);
+        assert_eq!(db.run(r#"JSON.NUMINCRBY("j","$.s","1")"#), "Error: Value at path is not a number");
+        assert_eq!(db.run(r#"JSON.ARRAPPEND("j","$.a","1")"#), "Error: Value at path is not an array");
+        assert_eq!(db.run(r#"JSON.GET("j","$.a[")"#), "Error: Invalid JSON path");
+        assert_eq!(db.run(r#"JSON.GET("j","$..a")"#), "Error: Invalid JSON path");
+        assert!(db.run(r#"JSON.SET("j","$.a","{")"#).starts_with("Error: Invalid JSON: "));
+        assert_eq!(db.run(r#"JSON.SET("new","$.a","1")"#), "Error: New documents must be set at the root path");
+        assert_eq!(db.run(r#"JSON.GET("j")"#), r#"{"a":{"b":1},"s":"x","l":[]}"#);
+    }
+
+    #[test]
+    fn changes_are_replayed_after_a_restart() {
+        let mut db = TestDb::new("json_test_replay");
+        db.run(r#"JSON.SET("j","$","{\"a\":{\"b\":1},\"l\":[1,2,3]}")"#);
+        db.run(r#"JSON.SET("j","$.a.c","[true]")"#);
+        db.run(r#"JSON.NUMINCRBY("j","$.a.b","41")"#);
+        db.run(r#"JSON.ARRAPPEND("j","$.l","4")"#);
+        db.run(r#"JSON.DEL("j","$.l[-4]")"#);
+        db.run(r#"JSON.SET("r","$","1")"#);
+        db.run(r#"JSON.SET("r","$","\"root\"")"#);
+        let version = db.version("j");
+
+        db.reload();
+        assert_eq!(db.run(r#"JSON.GET("j")"#), r#"{"a":{"b":42,"c":[true]},"l":[2,3,4]}"#);
+        assert_eq!(db.version("j"), version);
+        assert_eq!(db.run(r#"JSON.GET("r")"#), r#""root""#);
+    }
+}
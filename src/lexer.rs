@@ -0,0 +1,312 @@
+// =======================================================
+// 🧠 INFO: Command Language Lexer
+// =======================================================
+// Statements look like NAME(arg, arg, ...). An argument is one of:
+// - a quoted string: "a, \"quoted\" value\n" with escapes \" \\ \n \r \t \0 \xNN
+// - a bare word: 10s, NX, 42 (anything up to whitespace , ( ) or ")
+// - a binary literal: {N}, whose N-byte payload follows the command line
+// Whitespace is allowed between all tokens.
+
+use std::fmt;
+
+/// A single argument of a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    // Quoted string or bare word, with escapes already decoded.
+    Bytes(Vec<u8>),
+    // Binary literal announcing a payload of this many bytes.
+    Literal(usize),
+}
+
+/// A parsed statement: command name and its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub command: String,
+    pub args: Vec<Arg>,
+}
+
+/// A syntax error, with the 1-based column (in characters) where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parse error at column {}: {}", self.column, self.message)
+    }
+}
+
+/// Parses a statement of the form NAME(arg, ...).
+pub fn parse(input: &str) -> Result<Statement, ParseError> {
+    let mut lexer = Lexer { chars: input.chars().collect(), pos: 0 };
+
+    lexer.skip_whitespace();
+    let command = lexer.ident()?;
+
+    lexer.skip_whitespace();
+    lexer.expect('(')?;
+
+    let mut args = Vec::new();
+    lexer.skip_whitespace();
+    if lexer.peek() == Some(')') {
+        lexer.pos += 1;
+    } else {
+        loop {
+            lexer.skip_whitespace();
+            args.push(lexer.arg()?);
+            lexer.skip_whitespace();
+            match lexer.peek() {
+                Some(',') => lexer.pos += 1,
+                Some(')') => {
+                    lexer.pos += 1;
+                    break;
+                }
+                Some(c) => return Err(lexer.error(format!("Expected ',' or ')' but found '{}'", c))),
+                None => return Err(lexer.error("Expected ',' or ')' but input ended")),
+            }
+        }
+    }
+
+    lexer.skip_whitespace();
+    if let Some(c) = lexer.peek() {
+        return Err(lexer.error(format!("Unexpected '{}' after ')'", c)));
+    }
+
+    Ok(Statement { command, args })
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError { column: self.pos + 1, message: message.into() }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("Expected '{}' but found '{}'", expected, c))),
+            None => Err(self.error(format!("Expected '{}' but input ended", expected))),
+        }
+    }
+
+    /// Command name: letters, digits, '_' and '.', starting with a letter.
+    fn ident(&mut self) -> Result<String, ParseError> {
+        if !self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            return Err(self.error("Expected command name"));
+        }
+
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            self.pos += 1;
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn arg(&mut self) -> Result<Arg, ParseError> {
+        match self.peek() {
+            Some('"') => self.quoted().map(Arg::Bytes),
+            Some('{') => self.literal(),
+            Some(c) if is_word_char(c) => Ok(Arg::Bytes(self.word().into_bytes())),
+            Some(c) => Err(self.error(format!("Expected argument but found '{}'", c))),
+            None => Err(self.error("Expected argument but input ended")),
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(is_word_char) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Binary literal header: {N}.
+    fn literal(&mut self) -> Result<Arg, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let digits_start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[digits_start..self.pos].iter().collect();
+
+        if digits.is_empty() || self.peek() != Some('}') {
+            self.pos = start;
+            return Err(self.error("Invalid binary literal (expected {N})"));
+        }
+        self.pos += 1;
+
+        digits.parse().map(Arg::Literal).map_err(|_| ParseError {
+            column: start + 1,
+            message: "Binary literal length too large".to_string(),
+        })
+    }
+
+    fn quoted(&mut self) -> Result<Vec<u8>, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let mut out = Vec::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    return Err(ParseError {
+                        column: start + 1,
+                        message: "Unterminated string".to_string(),
+                    });
+                }
+            };
+            self.pos += 1;
+
+            match c {
+                '"' => return Ok(out),
+                '\\' => self.escape(&mut out)?,
+                _ => {
+                    let mut buf = [0; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+    }
+
+    /// Decodes the escape sequence following a backslash.
+    fn escape(&mut self, out: &mut Vec<u8>) -> Result<(), ParseError> {
+        let backslash = self.pos - 1;
+        let c = self.peek().ok_or_else(|| self.error("Unterminated escape sequence"))?;
+        self.pos += 1;
+
+        match c {
+            '"' => out.push(b'"'),
+            '\\' => out.push(b'\\'),
+            'n' => out.push(b'\n'),
+            'r' => out.push(b'\r'),
+            't' => out.push(b'\t'),
+            '0' => out.push(0),
+            'x' => {
+                let hex: String = self.chars.iter().skip(self.pos).take(2).collect();
+                // from_str_radix alone would also accept a sign, as in \x+f
+                let byte = (hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                    .then(|| u8::from_str_radix(&hex, 16).ok())
+                    .flatten()
+                    .ok_or(ParseError {
+                        column: backslash + 1,
+                        message: "Invalid hex escape (expected \\xNN)".to_string(),
+                    })?;
+                self.pos += 2;
+                out.push(byte);
+            }
+            other => {
+                return Err(ParseError {
+                    column: backslash + 1,
+                    message: format!("Unknown escape sequence '\\{}'", other),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, ',' | '(' | ')' | '"' | '{' | '}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &str) -> Vec<Arg> {
+        parse(input).unwrap().args
+    }
+
+    fn bytes(s: &[u8]) -> Arg {
+        Arg::Bytes(s.to_vec())
+    }
+
+    /// Column and message of the error `input` fails with.
+    fn error(input: &str) -> (usize, String) {
+        let e = parse(input).unwrap_err();
+        (e.column, e.message)
+    }
+
+    #[test]
+    fn parses_statements() {
+        assert_eq!(
+            parse(" SET ( \"key\" , 10s,NX ) ").unwrap(),
+            Statement { command: "SET".to_string(), args: vec![bytes(b"key"), bytes(b"10s"), bytes(b"NX")] }
+        );
+        assert_eq!(parse("JSON.GET(\"doc\")").unwrap().command, "JSON.GET");
+        assert_eq!(parse("KEYS()").unwrap().args, Vec::new());
+        assert_eq!(args("SET(\"\",\"a, (b)\")"), vec![bytes(b""), bytes(b"a, (b)")]);
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(args(r#"SET("\"\\\n\r\t\0")"#), vec![bytes(b"\"\\\n\r\t\0")]);
+        assert_eq!(args(r#"SET("\x00\xfF\x41")"#), vec![bytes(b"\x00\xff\x41")]);
+        assert_eq!(args("SET(\"é\")"), vec![bytes("é".as_bytes())]);
+    }
+
+    #[test]
+    fn parses_binary_literals() {
+        assert_eq!(args("SET(\"k\",{5}, {0})"), vec![bytes(b"k"), Arg::Literal(5), Arg::Literal(0)]);
+    }
+
+    #[test]
+    fn reports_error_columns() {
+        assert_eq!(error(""), (1, "Expected command name".to_string()));
+        assert_eq!(error("1SET()"), (1, "Expected command name".to_string()));
+        assert_eq!(error("GET"), (4, "Expected '(' but input ended".to_string()));
+        assert_eq!(error("GET \"a\")"), (5, "Expected '(' but found '\"'".to_string()));
+        assert_eq!(error("GET(\"a\" \"b\")"), (9, "Expected ',' or ')' but found '\"'".to_string()));
+        assert_eq!(error("GET(\"a\","), (9, "Expected argument but input ended".to_string()));
+        assert_eq!(error("GET(,)"), (5, "Expected argument but found ','".to_string()));
+        assert_eq!(error("GET(a) b"), (8, "Unexpected 'b' after ')'".to_string()));
+        assert_eq!(error("GET(\"abc"), (5, "Unterminated string".to_string()));
+    }
+
+    #[test]
+    fn reports_escape_errors_at_the_backslash() {
+        assert_eq!(error(r#"SET("ab\q")"#), (8, "Unknown escape sequence '\\q'".to_string()));
+        assert_eq!(error(r#"SET("\x4")"#), (6, "Invalid hex escape (expected \\xNN)".to_string()));
+        assert_eq!(error(r#"SET("\xzz")"#), (6, "Invalid hex escape (expected \\xNN)".to_string()));
+        assert_eq!(error(r#"SET("\x+f")"#), (6, "Invalid hex escape (expected \\xNN)".to_string()));
+        assert_eq!(error("SET(\"\\"), (7, "Unterminated escape sequence".to_string()));
+    }
+
+    #[test]
+    fn columns_count_characters_not_bytes() {
+        assert_eq!(error("SET(\"é\" x)"), (9, "Expected ',' or ')' but found 'x'".to_string()));
+    }
+
+    #[test]
+    fn rejects_malformed_literals() {
+        assert_eq!(error("SET(\"k\",{})"), (9, "Invalid binary literal (expected {N})".to_string()));
+        assert_eq!(error("SET(\"k\",{5)"), (9, "Invalid binary literal (expected {N})".to_string()));
+        assert_eq!(error("SET(\"k\",{x})"), (9, "Invalid binary literal (expected {N})".to_string()));
+        assert_eq!(
+            error("SET(\"k\",{99999999999999999999999})"),
+            (9, "Binary literal length too large".to_string())
+        );
+    }
+}
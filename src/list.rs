@@ -0,0 +1,331 @@
+// =======================================================
+// 🧠 INFO: List Commands
+// =======================================================
+// Lists are stored as `Value::List` and never left empty: the key is deleted
+// together with its last element. Indexes may be negative to count from the
+// end (-1 is the last element). Mutations log what they pushed, how many
+// elements they popped or the range they trimmed to, not the whole list.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DbInstance, JsonBytes, Keyspace, Value, ValueWithExpiry};
+use crate::parser::{key_arg, log_change, no_database, parse_integer, purge_if_expired, usage, wrong_type};
+use crate::reply::{ErrorCode, Reply};
+use crate::wal::Change;
+
+type List = VecDeque<Vec<u8>>;
+
+/// Which end of a list a push or pop works on.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum End {
+    Left,
+    Right,
+}
+
+/// LPUSH/RPUSH("key","value",...) - creates the list if needed and replies
+/// with its new length; the TTL of an existing list is kept
+pub fn push(args: Vec<Vec<u8>>, end: End, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 2 {
+        return match end {
+            End::Left => usage("LPUSH(\"key\",\"value\",...)"),
+            End::Right => usage("RPUSH(\"key\",\"value\",...)"),
+        };
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let entry = db
+                .entry(key.clone())
+                .or_insert_with(|| ValueWithExpiry::new(Value::List(VecDeque::new()), None));
+            let Value::List(items) = &mut entry.value else {
+                return wrong_type();
+            };
+            let values: Vec<_> = args.collect();
+            for value in &values {
+                match end {
+                    End::Left => items.push_front(value.clone()),
+                    End::Right => items.push_back(value.clone()),
+                }
+            }
+            let len = items.len();
+            let values = values.into_iter().map(JsonBytes).collect();
+            db_instance.log_change(&key, entry, Change::ListPush { end, values });
+            Reply::Int(len as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// LPOP/RPOP("key",["count"]) - without a count, replies with the removed
+/// element; with one, with an array of up to `count` elements. Replies nil
+/// if the key doesn't exist
+pub fn pop(args: Vec<Vec<u8>>, end: End, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() || args.len() > 2 {
+        return match end {
+            End::Left => usage("LPOP(\"key\",[\"count\"])"),
+            End::Right => usage("RPOP(\"key\",[\"count\"])"),
+        };
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let count = match args.next() {
+        Some(count) => match parse_integer(&count).and_then(|n| usize::try_from(n).ok()) {
+            Some(count) => Some(count),
+            None => return Reply::error(ErrorCode::InvalidArgument, "Error: Count must be a non-negative integer"),
+        },
+        None => None,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let items = match list_mut(&mut db, db_instance, &key) {
+                Ok(Some(items)) => items,
+                Ok(None) => return Reply::Nil,
+                Err(e) => return e,
+            };
+
+            let mut popped = Vec::new();
+            while popped.len() < count.unwrap_or(1) {
+                let item = match end {
+                    End::Left => items.pop_front(),
+                    End::Right => items.pop_back(),
+                };
+                match item {
+                    Some(item) => popped.push(item),
+                    None => break,
+                }
+            }
+            if !popped.is_empty() {
+                log_change(&mut db, db_instance, &key, Change::ListPop { end, count: popped.len() });
+            }
+
+            match count {
+                Some(_) => Reply::Array(popped.into_iter().map(Reply::Value).collect()),
+                None => popped.pop().map_or(Reply::Nil, Reply::Value),
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// LRANGE("key","start","stop") - replies with the elements from `start` to
+/// `stop` inclusive (empty if the key doesn't exist)
+pub fn range(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "LRANGE(\"key\",\"start\",\"stop\")";
+    if args.len() != 3 {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let (start, stop) = match index_args(args) {
+        Ok(indexes) => indexes,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match list_mut(&mut db, db_instance, &key) {
+                Ok(Some(items)) => match index_range(items.len(), start, stop) {
+                    Some((first, last)) => {
+                        Reply::Array(items.range(first..=last).cloned().map(Reply::Value).collect())
+                    }
+                    None => Reply::Array(Vec::new()),
+                },
+                Ok(None) => Reply::Array(Vec::new()),
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// LLEN("key") - replies with the length of the list (0 if the key doesn't exist)
+pub fn len(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("LLEN(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match list_mut(&mut db, db_instance, &key) {
+                Ok(items) => Reply::Int(items.map_or(0, |items| items.len() as i64)),
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// LTRIM("key","start","stop") - keeps only the elements from `start` to
+/// `stop` inclusive, deleting the key if none are left
+pub fn trim(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 3 {
+        return usage("LTRIM(\"key\",\"start\",\"stop\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let (start, stop) = match index_args(args) {
+        Ok(indexes) => indexes,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let items = match list_mut(&mut db, db_instance, &key) {
+                Ok(Some(items)) => items,
+                Ok(None) => return Reply::Ok,
+                Err(e) => return e,
+            };
+
+            let len = items.len();
+            let (first, last) = match index_range(len, start, stop) {
+                Some((first, last)) => {
+                    if first == 0 && last == len - 1 {
+                        return Reply::Ok;
+                    }
+                    items.truncate(last + 1);
+                    items.drain(..first);
+                    (first, last)
+                }
+                None => {
+                    items.clear();
+                    (len, len)
+                }
+            };
+            log_change(&mut db, db_instance, &key, Change::ListTrim { first, last });
+            Reply::Ok
+        }
+        None => no_database(),
+    }
+}
+
+/// LINDEX("key","index") - replies with the element at `index`, or nil if
+/// it is out of range or the key doesn't exist
+pub fn index(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("LINDEX(\"key\",\"index\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let Some(index) = parse_integer(&args.next().unwrap()) else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Index must be an integer");
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match list_mut(&mut db, db_instance, &key) {
+                Ok(Some(items)) => match index_range(items.len(), index, index) {
+                    Some((position, _)) => Reply::Value(items[position].clone()),
+                    None => Reply::Nil,
+                },
+                Ok(None) => Reply::Nil,
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// Looks up the list stored at `key`, purging it first if it has expired.
+/// Replies WRONG_TYPE if the key holds another type.
+fn list_mut<'a>(db: &'a mut Keyspace, db_instance: &DbInstance, key: &str) -> Result<Option<&'a mut List>, Reply> {
+    purge_if_expired(db, db_instance, key);
+    match db.get_mut(key).map(|entry| &mut entry.value) {
+        Some(Value::List(items)) => Ok(Some(items)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Parses the "start","stop" arguments of LRANGE and LTRIM.
+pub fn index_args(mut args: impl Iterator<Item = Vec<u8>>) -> Result<(i64, i64), Reply> {
+    let start = parse_integer(&args.next().unwrap_or_default());
+    let stop = parse_integer(&args.next().unwrap_or_default());
+    match (start, stop) {
+        (Some(start), Some(stop)) => Ok((start, stop)),
+        _ => Err(Reply::error(ErrorCode::InvalidArgument, "Error: Start and stop must be integers")),
+    }
+}
+
+/// Resolves possibly negative `start` and `stop` indexes against a list of
+/// `len` elements, clamping them to its bounds. Returns the first and last
+/// position, or `None` if the range is empty.
+pub fn index_range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::testing::TestDb;
+
+    #[test]
+    fn negative_indexes_count_from_the_end() {
+        let db = TestDb::new("list_test_indexes");
+        db.run("RPUSH(\"l\",\"a\",\"b\",\"c\",\"d\")");
+        assert_eq!(db.run("LRANGE(\"l\",\"0\",\"-1\")"), "a\nb\nc\nd");
+        assert_eq!(db.run("LRANGE(\"l\",\"-2\",\"-1\")"), "c\nd");
+        assert_eq!(db.run("LRANGE(\"l\",\"-100\",\"1\")"), "a\nb");
+        assert_eq!(db.run("LRANGE(\"l\",\"2\",\"100\")"), "c\nd");
+        assert_eq!(db.run("LRANGE(\"l\",\"-1\",\"-2\")"), "");
+        assert_eq!(db.run("LINDEX(\"l\",\"-1\")"), "d");
+        assert_eq!(db.run("LINDEX(\"l\",\"-4\")"), "a");
+        assert_eq!(db.run("LINDEX(\"l\",\"-5\")"), "(nil)");
+        assert_eq!(db.run("LINDEX(\"l\",\"4\")"), "(nil)");
+
+        assert_eq!(db.run("LTRIM(\"l\",\"1\",\"-2\")"), "OK");
+        assert_eq!(db.run("LRANGE(\"l\",\"0\",\"-1\")"), "b\nc");
+        assert_eq!(db.run("LTRIM(\"l\",\"-1\",\"0\")"), "OK");
+        assert_eq!(db.run("EXISTS(\"l\")"), "0");
+    }
+
+    #[test]
+    fn changes_are_replayed_after_a_restart() {
+        let mut db = TestDb::new("list_test_replay");
+        assert_eq!(db.run("RPUSH(\"l\",\"c\",\"d\",\"e\",\"f\")"), "4");
+        assert_eq!(db.run("LPUSH(\"l\",\"b\",\"a\")"), "6");
+        assert_eq!(db.run("LPOP(\"l\")"), "a");
+        assert_eq!(db.run("RPOP(\"l\",\"2\")"), "f\ne");
+        assert_eq!(db.run("LTRIM(\"l\",\"1\",\"-1\")"), "OK");
+        db.run("RPUSH(\"gone\",\"x\")");
+        db.run("LPOP(\"gone\")");
+        let version = db.version("l");
+
+        db.reload();
+        assert_eq!(db.run("LRANGE(\"l\",\"0\",\"-1\")"), "c\nd");
+        assert_eq!(db.version("l"), version);
+        assert_eq!(db.run("EXISTS(\"gone\")"), "0");
+    }
+}
@@ -2,28 +2,89 @@
 // 🧠 INFO: Main Imports and Module Declarations
 // =======================================================
 mod cleaner;
+mod config;
 mod db;
+mod flusher;
+mod glob;
+mod hash;
+mod http;
+mod index;
+mod json;
+mod lexer;
+mod list;
 mod logger;
 mod parser;
-use bcrypt::{hash, verify, DEFAULT_COST};
-use crate::db::DbMap;
+mod reply;
+mod resp;
+mod set;
+mod snapshot;
+mod wal;
+mod zset;
+use crate::db::{CreateDbError, DbMap};
+use crate::reply::{ErrorCode, Reply};
 use db::DbInstance;
 use std::collections::HashMap;
 use std::env;
-use std::path::Path;
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
 use crate::logger::log_info;
+
+/// Reads the payloads of the binary literals announced by a command line,
+/// each being exactly `len` bytes followed by a line break.
+async fn read_literals<R: AsyncBufRead + Unpin>(reader: &mut R, lengths: &[usize]) -> std::io::Result<Vec<Vec<u8>>> {
+    let total = lengths.iter().try_fold(0usize, |total, &len| total.checked_add(len));
+    if total.is_none_or(|total| total > parser::MAX_LITERAL_SIZE) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Binary literals exceed the {} byte limit per command", parser::MAX_LITERAL_SIZE),
+        ));
+    }
+
+    let mut literals = Vec::with_capacity(lengths.len());
+    for &len in lengths {
+        literals.push(read_literal(reader, len).await?);
+    }
+    Ok(literals)
+}
+
+/// Reads the payload of a binary literal: exactly `len` bytes followed by a line break.
+/// The buffer grows as bytes arrive rather than being sized from the announced length.
+async fn read_literal<R: AsyncBufRead + Unpin>(reader: &mut R, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    (&mut *reader).take(len as u64).read_to_end(&mut payload).await?;
+    if payload.len() < len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "Connection closed inside a binary literal",
+        ));
+    }
+
+    let mut terminator = Vec::new();
+    reader.read_until(b'\n', &mut terminator).await?;
+    if !terminator.trim_ascii().is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Binary literal longer than announced",
+        ));
+    }
+    Ok(payload)
+}
+
+/// Writes a reply in the connection's response mode (JSON or plain text),
+/// followed by a line break.
+async fn send_reply<W: AsyncWrite + Unpin>(writer: &mut W, reply: &Reply, json_mode: bool) -> std::io::Result<()> {
+    let mut out = if json_mode { reply.to_json() } else { reply.to_text() };
+    out.push(b'\n');
+    writer.write_all(&out).await
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Parse port from args or default to 4000
+    // Parse port and persistence options from args (port defaults to 4000)
     let args: Vec<String> = env::args().collect();
-    let port = args
-        .get(1)
-        .map(|s| s.to_string())
-        .unwrap_or("4000".to_string());
-    let address = format!("0.0.0.0:{}", port);
+    let config = config::init(&args).map_err(anyhow::Error::msg)?;
+    let address = format!("0.0.0.0:{}", config.port);
 
     // Shared state for all databases
     let all_dbs: DbMap = Arc::new(Mutex::new(HashMap::new()));
@@ -31,6 +92,17 @@ async fn main() -> anyhow::Result<()> {
     // Start cleaner thread
     cleaner::start_cleaner(all_dbs.clone()).await;
 
+    // Start flusher that snapshots modified databases
+    flusher::start_flusher(all_dbs.clone()).await;
+
+    // Start write-ahead log syncer (only active with the everysec fsync policy)
+    wal::start_syncer(all_dbs.clone()).await;
+
+    // Start HTTP/JSON API if a port was given
+    if let Some(http_port) = &config.http_port {
+        http::start_server(format!("0.0.0.0:{}", http_port), all_dbs.clone()).await?;
+    }
+
     // Create TCP listener
     let listener = TcpListener::bind(&address).await?;
     log_info(&format!("Server running on {}", address));
@@ -49,13 +121,24 @@ async fn main() -> anyhow::Result<()> {
         let all_dbs = all_dbs.clone();
         // Spawn new task for each connection
         tokio::spawn(async move {
+            // Redis clients open with a RESP array ('*'); hand those to the RESP handler
+            let mut first_byte = [0u8; 1];
+            if let Ok(1) = socket.peek(&mut first_byte).await
+                && first_byte[0] == b'*'
+            {
+                resp::handle_connection(socket, all_dbs).await;
+                return;
+            }
+
             let (reader, mut writer) = socket.split();
             let mut reader = BufReader::new(reader);
-            let mut line = String::new();
+            let mut raw_line = Vec::new();
             let mut current_db_instance: Option<Arc<DbInstance>> = None;
+            // Replies are plain text until the client switches with "proto json"
+            let mut json_mode = false;
             loop {
-                line.clear();
-                let bytes_read = match reader.read_line(&mut line).await {
+                raw_line.clear();
+                let bytes_read = match reader.read_until(b'\n', &mut raw_line).await {
                     Ok(0) => break, // Connection closed by client
                     Ok(n) => n,
                     Err(e) => {
@@ -68,46 +151,65 @@ async fn main() -> anyhow::Result<()> {
                     break;
                 }
 
-                let parts: Vec<&str> = line.trim().split_whitespace().collect();
+                // Commands are text; binary payloads travel as literals after the line
+                let line = String::from_utf8_lossy(&raw_line).into_owned();
+
+                let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.is_empty() {
                     continue;
                 }
 
                 match parts[0] {
+                    // Switch the response format of this connection
+                    "proto" if parts.len() == 2 => {
+                        let reply = match parts[1] {
+                            "json" => {
+                                json_mode = true;
+                                Reply::Ok
+                            }
+                            "text" => {
+                                json_mode = false;
+                                Reply::Ok
+                            }
+                            other => Reply::error(
+                                ErrorCode::InvalidArgument,
+                                format!("Error: Unknown protocol '{}' (use text or json)", other),
+                            ),
+                        };
+                        if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
+                            eprintln!("Error writing to socket: {}", e);
+                            break;
+                        }
+                    }
                     // Create a new database
                     "create" if parts.len() == 2 => {
                         // Check if a database is already selected
                         if current_db_instance.is_some() {
-                            if let Err(e) = writer
-                                .write_all(
-                                    b"Cannot create a database. A database is already selected.\n",
-                                )
-                                .await
-                            {
+                            let reply = Reply::error(
+                                ErrorCode::DbAlreadySelected,
+                                "Cannot create a database. A database is already selected.",
+                            );
+                            if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                 eprintln!("Error writing to socket: {}", e);
                                 break;
                             }
                         } else {
                             let db_name = parts[1].to_string();
-                            let path = format!("dbs/{}.json", db_name);
-                            if Path::new(&path).exists() {
-                                if let Err(e) = writer
-                                    .write_all(
-                                        format!("Error: Database '{}' already exists\n", db_name)
-                                            .as_bytes(),
-                                    )
-                                    .await
-                                {
+                            if !db::is_valid_name(&db_name) || DbInstance::exists_on_disk(&db_name) {
+                                let reply = if db::is_valid_name(&db_name) {
+                                    Reply::error(ErrorCode::DbExists, format!("Error: Database '{}' already exists", db_name))
+                                } else {
+                                    Reply::error(ErrorCode::InvalidDbName, format!("Error: Invalid database name '{}'", db_name))
+                                };
+                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                     eprintln!("Error writing to socket: {}", e);
                                     break;
                                 }
                                 continue;
                             }
                             // Ask for authentication preference
-                            if let Err(e) = writer
-                                .write_all(b"Do you want authentication (yes/no)?\n")
-                                .await
-                            {
+                            let prompt = Reply::Prompt("Do you want authentication (yes/no)?".to_string());
+                            if let Err(e) = send_reply(&mut writer, &prompt, json_mode).await {
                                 eprintln!("Error writing to socket: {}", e);
                                 break;
                             }
@@ -119,8 +221,9 @@ async fn main() -> anyhow::Result<()> {
                             }
                             let auth_option = auth_line.trim().to_lowercase() == "yes";
                             // If authentication is required, ask for username and password
-                            let db_instance = if auth_option {
-                                if let Err(e) = writer.write_all(b"Enter username:\n").await {
+                            let created = if auth_option {
+                                let prompt = Reply::Prompt("Enter username:".to_string());
+                                if let Err(e) = send_reply(&mut writer, &prompt, json_mode).await {
                                     eprintln!("Error writing to socket: {}", e);
                                     break;
                                 }
@@ -132,7 +235,8 @@ async fn main() -> anyhow::Result<()> {
                                 }
                                 let username = username_line.trim().to_string();
 
-                                if let Err(e) = writer.write_all(b"Enter password:\n").await {
+                                let prompt = Reply::Prompt("Enter password:".to_string());
+                                if let Err(e) = send_reply(&mut writer, &prompt, json_mode).await {
                                     eprintln!("Error writing to socket: {}", e);
                                     break;
                                 }
@@ -143,36 +247,28 @@ async fn main() -> anyhow::Result<()> {
                                     break;
                                 }
                                 let password = password_line.trim().to_string();
-                                let hashed_password = match hash(&password, DEFAULT_COST) {
-                                    Ok(hashed) => hashed,
-                                    Err(e) => {
-                                        eprintln!("Error hashing password: {}", e);
-                                        if let Err(e) = writer.write_all(b"Error creating database\n").await {
-                                            eprintln!("Error writing to socket: {}", e);
-                                        }
-                                        break;
-                                    }
-                                };
-                                db::DbInstance::new(
-                                    db_name.clone(),
-                                    true,
-                                    Some(username),
-                                    Some(hashed_password),
-                                )
+                                db::create_database(&all_dbs, &db_name, Some((username, password)))
                             } else {
-                                db::DbInstance::new(db_name.clone(), false, None, None)
+                                db::create_database(&all_dbs, &db_name, None)
                             };
 
-                            // Insert new database into shared state
-                            {
-                                let mut dbs = all_dbs.lock().unwrap();
-                                dbs.insert(db_name, db_instance);
+                            if let Err(error) = created {
+                                let code = match error {
+                                    CreateDbError::InvalidName(_) => ErrorCode::InvalidDbName,
+                                    CreateDbError::AlreadyExists(_) => ErrorCode::DbExists,
+                                    CreateDbError::Internal => ErrorCode::Internal,
+                                };
+                                let reply = Reply::error(code, error.to_string());
+                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
+                                    eprintln!("Error writing to socket: {}", e);
+                                    break;
+                                }
+                                continue;
                             }
 
                             // Confirm database creation
-                            if let Err(e) =
-                                writer.write_all(b"Database created successfully\n").await
-                            {
+                            let reply = Reply::Status("Database created successfully".to_string());
+                            if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                 eprintln!("Error writing to socket: {}", e);
                                 break;
                             }
@@ -182,29 +278,17 @@ async fn main() -> anyhow::Result<()> {
                     "use" if parts.len() == 2 => {
                         // Check if a database is already selected
                         if current_db_instance.is_some() {
-                            if let Err(e) = writer.write_all(b"Cannot use a different database. A database is already selected.\n").await {
+                            let reply = Reply::error(
+                                ErrorCode::DbAlreadySelected,
+                                "Cannot use a different database. A database is already selected.",
+                            );
+                            if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                 eprintln!("Error writing to socket: {}", e);
                                 break;
                             }
                         } else {
                             let db_name = parts[1];
-                            let db_instance = {
-                                let mut dbs = all_dbs.lock().unwrap();
-
-                                // Try to get from memory first
-                                if let Some(db) = dbs.get(db_name) {
-                                    Some(db.clone())
-                                } else {
-                                    // If not in memory, try to load from file
-                                    if let Some(db) = db::DbInstance::load_from_file(db_name) {
-                                        let db_clone = db.clone();
-                                        dbs.insert(db_name.to_string(), db);
-                                        Some(db_clone)
-                                    } else {
-                                        None
-                                    }
-                                }
-                            };
+                            let db_instance = db::get_or_load(&all_dbs, db_name);
 
                             match db_instance {
                                 Some(db_instance) => {
@@ -217,7 +301,8 @@ async fn main() -> anyhow::Result<()> {
                                         while !authenticated && auth_attempts < MAX_AUTH_ATTEMPTS {
                                             auth_attempts += 1;
 
-                                            if let Err(e) = writer.write_all(b"Username:\n").await {
+                                            let prompt = Reply::Prompt("Username:".to_string());
+                                            if let Err(e) = send_reply(&mut writer, &prompt, json_mode).await {
                                                 eprintln!("Error writing to socket: {}", e);
                                                 break;
                                             }
@@ -231,7 +316,8 @@ async fn main() -> anyhow::Result<()> {
                                             }
                                             let username = username_line.trim();
 
-                                            if let Err(e) = writer.write_all(b"Password:\n").await {
+                                            let prompt = Reply::Prompt("Password:".to_string());
+                                            if let Err(e) = send_reply(&mut writer, &prompt, json_mode).await {
                                                 eprintln!("Error writing to socket: {}", e);
                                                 break;
                                             }
@@ -244,35 +330,32 @@ async fn main() -> anyhow::Result<()> {
                                                 break;
                                             }
                                             let password = password_line.trim();
-                                            let is_valid = match verify(password, db_instance.password.as_deref().unwrap_or("")) {
+                                            let is_valid = match db_instance.check_credentials(username, password).await {
                                                 Ok(valid) => valid,
                                                 Err(e) => {
                                                     eprintln!("Error verifying password: {}", e);
-                                                    if let Err(e) = writer.write_all(b"Authentication error.\n").await {
+                                                    let reply = Reply::error(ErrorCode::Internal, "Authentication error.");
+                                                    if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                                         eprintln!("Error writing to socket: {}", e);
                                                     }
                                                     break;
                                                 }
                                             };
                                             
-                                            if db_instance.username.as_deref() == Some(username) && is_valid
-                                            {
+                                            if is_valid {
                                                 // If authentication successful, select database
                                                 authenticated = true;
                                                 current_db_instance =
                                                     Some(Arc::new(db_instance.clone()));
-                                                if let Err(e) = writer.write_all(format!("Authentication successful Using database '{}'\n", db_name).as_bytes()).await {
+                                                let reply = Reply::Status(format!("Authentication successful Using database '{}'", db_name));
+                                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                                     eprintln!("Error writing to socket: {}", e);
                                                     break;
                                                 }
                                             } else {
                                                 // If authentication failed, try again
-                                                if let Err(e) = writer
-                                                    .write_all(
-                                                        b"Authentication failed. Try again.\n",
-                                                    )
-                                                    .await
-                                                {
+                                                let reply = Reply::error(ErrorCode::AuthFailed, "Authentication failed. Try again.");
+                                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                                     eprintln!("Error writing to socket: {}", e);
                                                     break;
                                                 }
@@ -280,7 +363,11 @@ async fn main() -> anyhow::Result<()> {
                                         }
                                         // If authentication failed after max attempts, disconnect
                                         if !authenticated && auth_attempts >= MAX_AUTH_ATTEMPTS {
-                                            if let Err(e) = writer.write_all(b"Too many failed authentication attempts. Disconnecting.\n").await {
+                                            let reply = Reply::error(
+                                                ErrorCode::TooManyAuthAttempts,
+                                                "Too many failed authentication attempts. Disconnecting.",
+                                            );
+                                            if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                                 eprintln!("Error writing to socket: {}", e);
                                             }
                                             break;
@@ -288,26 +375,16 @@ async fn main() -> anyhow::Result<()> {
                                     } else {
                                         // If authentication is not required, select database
                                         current_db_instance = Some(Arc::new(db_instance.clone()));
-                                        if let Err(e) = writer
-                                            .write_all(
-                                                format!("Using database '{}'\n", db_name)
-                                                    .as_bytes(),
-                                            )
-                                            .await
-                                        {
+                                        let reply = Reply::Status(format!("Using database '{}'", db_name));
+                                        if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                             eprintln!("Error writing to socket: {}", e);
                                             break;
                                         }
                                     }
                                 }
                                 None => {
-                                    if let Err(e) = writer
-                                        .write_all(
-                                            format!("Database '{}' not found\n", db_name)
-                                                .as_bytes(),
-                                        )
-                                        .await
-                                    {
+                                    let reply = Reply::error(ErrorCode::DbNotFound, format!("Database '{}' not found", db_name));
+                                    if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                         eprintln!("Error writing to socket: {}", e);
                                         break;
                                     }
@@ -320,16 +397,18 @@ async fn main() -> anyhow::Result<()> {
                         let db_name = parts[1].to_string();
 
                         // Check if trying to drop the currently selected database
-                        if let Some(ref current_db) = current_db_instance {
-                            if current_db.name == db_name {
-                                if let Err(e) = writer.write_all(
-                    b"Cannot drop the currently selected database. Please 'use' another database first.\n"
-                ).await {
-                    eprintln!("Error writing to socket: {}", e);
-                    break;
-                }
-                                continue;
+                        if let Some(ref current_db) = current_db_instance
+                            && current_db.name == db_name
+                        {
+                            let reply = Reply::error(
+                                ErrorCode::DbInUse,
+                                "Cannot drop the currently selected database. Please 'use' another database first.",
+                            );
+                            if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
+                                eprintln!("Error writing to socket: {}", e);
+                                break;
                             }
+                            continue;
                         }
 
                         // First check if database exists without holding the lock across await
@@ -344,7 +423,7 @@ async fn main() -> anyhow::Result<()> {
 
                         // Handle file-based database case
                         let (db_instance, exists_in_memory) = if db_instance.is_none() {
-                            if Path::new(&format!("dbs/{}.json", db_name)).exists() {
+                            if DbInstance::exists_on_disk(&db_name) {
                                 (db::DbInstance::load_from_file(&db_name), false)
                             } else {
                                 (None, false)
@@ -367,7 +446,8 @@ async fn main() -> anyhow::Result<()> {
                                     while !authenticated && auth_attempts < MAX_AUTH_ATTEMPTS {
                                         auth_attempts += 1;
 
-                                        if let Err(e) = writer.write_all(b"Username:\n").await {
+                                        let prompt = Reply::Prompt("Username:".to_string());
+                                        if let Err(e) = send_reply(&mut writer, &prompt, json_mode).await {
                                             eprintln!("Error writing to socket: {}", e);
                                             break;
                                         }
@@ -379,7 +459,8 @@ async fn main() -> anyhow::Result<()> {
                                         }
                                         let input_username = username_line.trim();
 
-                                        if let Err(e) = writer.write_all(b"Password:\n").await {
+                                        let prompt = Reply::Prompt("Password:".to_string());
+                                        if let Err(e) = send_reply(&mut writer, &prompt, json_mode).await {
                                             eprintln!("Error writing to socket: {}", e);
                                             break;
                                         }
@@ -390,25 +471,23 @@ async fn main() -> anyhow::Result<()> {
                                             break;
                                         }
                                         let input_password = password_line.trim();
-                                        let is_valid = match verify(input_password, db_instance.password.as_deref().unwrap_or("")) {
+                                        let is_valid = match db_instance.check_credentials(input_username, input_password).await {
                                             Ok(valid) => valid,
                                             Err(e) => {
                                                 eprintln!("Error verifying password: {}", e);
-                                                if let Err(e) = writer.write_all(b"Authentication error.\n").await {
+                                                let reply = Reply::error(ErrorCode::Internal, "Authentication error.");
+                                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                                     eprintln!("Error writing to socket: {}", e);
                                                 }
                                                 break;
                                             }
                                         };
                                         
-                                        if db_instance.username.as_deref() == Some(input_username) && is_valid
-                                        {
+                                        if is_valid {
                                             authenticated = true;
                                         } else {
-                                            if let Err(e) = writer
-                                                .write_all(b"Authentication failed. Try again.\n")
-                                                .await
-                                            {
+                                            let reply = Reply::error(ErrorCode::AuthFailed, "Authentication failed. Try again.");
+                                            if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                                 eprintln!("Error writing to socket: {}", e);
                                                 break;
                                             }
@@ -421,54 +500,41 @@ async fn main() -> anyhow::Result<()> {
                                             let mut dbs = all_dbs.lock().unwrap();
                                             dbs.insert(db_name.clone(), db_instance);
                                         }
-                                        if let Err(e) = writer.write_all(
-                            b"Too many failed authentication attempts. Operation aborted.\n"
-                        ).await {
-                            eprintln!("Error writing to socket: {}", e);
-                        }
+                                        let reply = Reply::error(
+                                            ErrorCode::TooManyAuthAttempts,
+                                            "Too many failed authentication attempts. Operation aborted.",
+                                        );
+                                        if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
+                                            eprintln!("Error writing to socket: {}", e);
+                                        }
                                         continue;
                                     }
                                 }
 
-                                // Delete the database file
-                                let path = format!("dbs/{}.json", db_name);
-                                if let Err(e) = std::fs::remove_file(&path) {
+                                // Delete the database files
+                                if let Err(e) = DbInstance::delete_files(&db_name) {
                                     // Reinsert if it was in memory
                                     if exists_in_memory {
                                         let mut dbs = all_dbs.lock().unwrap();
                                         dbs.insert(db_name.clone(), db_instance);
                                     }
-                                    if let Err(e) = writer
-                                        .write_all(
-                                            format!("Error deleting database file: {}\n", e)
-                                                .as_bytes(),
-                                        )
-                                        .await
-                                    {
+                                    let reply = Reply::error(ErrorCode::Internal, format!("Error deleting database file: {}", e));
+                                    if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                         eprintln!("Error writing to socket: {}", e);
                                         break;
                                     }
                                     continue;
                                 }
 
-                                if let Err(e) = writer
-                                    .write_all(
-                                        format!("Database '{}' deleted successfully\n", db_name)
-                                            .as_bytes(),
-                                    )
-                                    .await
-                                {
+                                let reply = Reply::Status(format!("Database '{}' deleted successfully", db_name));
+                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                     eprintln!("Error writing to socket: {}", e);
                                     break;
                                 }
                             }
                             None => {
-                                if let Err(e) = writer
-                                    .write_all(
-                                        format!("Database '{}' not found\n", db_name).as_bytes(),
-                                    )
-                                    .await
-                                {
+                                let reply = Reply::error(ErrorCode::DbNotFound, format!("Database '{}' not found", db_name));
+                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                     eprintln!("Error writing to socket: {}", e);
                                     break;
                                 }
@@ -477,20 +543,40 @@ async fn main() -> anyhow::Result<()> {
                     }
                     // All other commands
                     _ => {
+                        // Read the payloads of any binary literals announced by the
+                        // command, even if it can't run, so they aren't taken for commands
+                        let literals = match parser::literal_lengths(&line) {
+                            Some(lengths) => read_literals(&mut reader, &lengths).await,
+                            None => Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                "Malformed command announcing a binary literal",
+                            )),
+                        };
+                        let literals = match literals {
+                            Ok(literals) => literals,
+                            // The stream can't be re-synchronised after a bad literal
+                            Err(e) => {
+                                eprintln!("Error reading binary literal: {}", e);
+                                let reply = Reply::error(ErrorCode::InvalidArgument, format!("Error: {}", e));
+                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
+                                    eprintln!("Error writing to socket: {}", e);
+                                }
+                                break;
+                            }
+                        };
+
                         match &current_db_instance {
                             Some(_db) => {
                                 // Parse command and execute
-                                let response =
-                                    parser::parse_statement(line.trim(), &current_db_instance);
-                                if let Err(e) =
-                                    writer.write_all(format!("{}\n", response).as_bytes()).await
-                                {
+                                let reply = parser::parse_statement(line.trim(), literals, &current_db_instance, &all_dbs);
+                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                     eprintln!("Error writing to socket: {}", e);
                                     break;
                                 }
                             }
                             None => {
-                                if let Err(e) = writer.write_all(b"Unknown command.\n").await {
+                                let reply = Reply::error(ErrorCode::UnknownCommand, "Unknown command.");
+                                if let Err(e) = send_reply(&mut writer, &reply, json_mode).await {
                                     eprintln!("Error writing to socket: {}", e);
                                     break;
                                 }
@@ -502,3 +588,40 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_literals_and_their_terminators() {
+        let mut input: &[u8] = b"hello\r\n\n\n\nrest";
+        let literals = read_literals(&mut input, &[5, 0, 1]).await.unwrap();
+        assert_eq!(literals, vec![b"hello".to_vec(), Vec::new(), b"\n".to_vec()]);
+        assert_eq!(input, b"rest");
+    }
+
+    #[tokio::test]
+    async fn rejects_literals_over_the_command_budget() {
+        let mut input: &[u8] = b"abc\n";
+        let half = parser::MAX_LITERAL_SIZE / 2 + 1;
+        let error = read_literals(&mut input, &[half, half]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        // Nothing is consumed before the budget is checked
+        assert_eq!(input, b"abc\n");
+
+        let error = read_literals(&mut input, &[usize::MAX, 1]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn rejects_short_and_overlong_literals() {
+        let mut input: &[u8] = b"abc";
+        let error = read_literals(&mut input, &[1024]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let mut input: &[u8] = b"abcdef\n";
+        let error = read_literals(&mut input, &[3]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
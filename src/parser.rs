@@ -1,128 +1,1941 @@
 // =======================================================
 // 🧠 INFO: Imports
 // =======================================================
-use crate::db::{DbInstance, ValueWithExpiry};
+use crate::db::{self, DbInstance, DbMap, Keyspace, Value, ValueWithExpiry};
+use crate::glob;
+use crate::hash;
+use crate::index;
+use crate::json;
+use crate::list::{self, End};
+use crate::set::{self, SetOp};
+use crate::zset;
+use crate::lexer::{self, Arg};
+use crate::reply::{ErrorCode, Reply};
+use crate::wal::{Change, LogRecord};
+use chrono::DateTime;
+use std::ops::Bound;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-/// Parses duration string (e.g. "5s", "10m", "1d") into Duration
-/// Format: <number><unit> where unit is s (seconds), m (minutes), or d (days)
-/// Returns error string if format is invalid
-fn parse_duration(s: &str) -> Result<Duration, String> {
+/// Parses a TTL string into the time left until the key expires.
+/// Accepted forms:
+/// - one or more <number><unit> parts, e.g. "500ms", "10s", "1h30m", where
+///   unit is ms, s, m (minutes), h, d or w
+/// - ISO-8601 durations, e.g. "PT15M", "P1DT12H", "P2W"
+/// - absolute RFC 3339 deadlines, e.g. "@2026-12-31T00:00:00Z" (deadlines in
+///   the past give a zero TTL, so the key expires straight away)
+///
+/// Returns error string if format is invalid or the TTL is too large
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
     if s.is_empty() {
         return Err("Empty TTL provided".to_string());
     }
 
-    // Split into numeric and unit parts
-    let num_part: String = s.chars().take_while(|c| c.is_digit(10)).collect();
-    let unit_part: String = s.chars().skip_while(|c| c.is_digit(10)).collect();
+    let millis = if let Some(deadline) = s.strip_prefix('@') {
+        let deadline = DateTime::parse_from_rfc3339(deadline)
+            .map_err(|_| "Invalid TTL deadline (use e.g. @2026-12-31T00:00:00Z)".to_string())?;
+        let deadline_ms = u64::try_from(deadline.timestamp_millis()).unwrap_or(0);
+        deadline_ms.saturating_sub(db::now_epoch_ms())
+    } else if let Some(iso) = s.strip_prefix('P') {
+        parse_iso_duration(iso)?
+    } else {
+        sum_duration_parts(s, |unit| match unit {
+            "ms" => Some(1),
+            "s" => Some(1000),
+            "m" => Some(60 * 1000),
+            "h" => Some(60 * 60 * 1000),
+            "d" => Some(24 * 60 * 60 * 1000),
+            "w" => Some(7 * 24 * 60 * 60 * 1000),
+            _ => None,
+        })
+        .map_err(|e| e.unwrap_or_else(|| "Invalid TTL unit (use ms, s, m, h, d or w)".to_string()))?
+    };
+
+    // The deadline has to be representable, not just the duration
+    let duration = Duration::from_millis(millis);
+    match Instant::now().checked_add(duration) {
+        Some(_) => Ok(duration),
+        None => Err(ttl_too_large()),
+    }
+}
+
+/// Parses the part of an ISO-8601 duration after the leading "P": weeks and
+/// days, then optionally "T" followed by hours, minutes and seconds.
+fn parse_iso_duration(s: &str) -> Result<u64, String> {
+    let invalid = || "Invalid ISO-8601 duration (use e.g. PT15M or P1DT12H)".to_string();
+    let (date, time) = match s.split_once('T') {
+        Some((_, "")) => return Err(invalid()),
+        Some((date, time)) => (date, time),
+        None => (s, ""),
+    };
+    if date.is_empty() && time.is_empty() {
+        return Err(invalid());
+    }
+
+    let date_ms = sum_duration_parts(date, |unit| match unit {
+        "W" => Some(7 * 24 * 60 * 60 * 1000),
+        "D" => Some(24 * 60 * 60 * 1000),
+        _ => None,
+    });
+    let time_ms = sum_duration_parts(time, |unit| match unit {
+        "H" => Some(60 * 60 * 1000),
+        "M" => Some(60 * 1000),
+        "S" => Some(1000),
+        _ => None,
+    });
+    match (date_ms, time_ms) {
+        (Ok(date_ms), Ok(time_ms)) => date_ms.checked_add(time_ms).ok_or_else(ttl_too_large),
+        (Err(e), _) | (_, Err(e)) => Err(e.unwrap_or_else(invalid)),
+    }
+}
+
+/// Sums a sequence of <number><unit> parts (e.g. "1h30m") in milliseconds,
+/// using `unit_millis` to look up the length of each unit. Fails with `None`
+/// for an unknown unit and with a message for anything else.
+fn sum_duration_parts(s: &str, unit_millis: impl Fn(&str) -> Option<u64>) -> Result<u64, Option<String>> {
+    let mut total: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return Err(Some("Invalid TTL number".to_string()));
+        }
+        let num = rest[..digits].parse::<u64>().map_err(|_| Some(ttl_too_large()))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_alphabetic()).len();
+        let factor = unit_millis(&rest[..unit_len]).ok_or(None)?;
+        rest = &rest[unit_len..];
+
+        total = num
+            .checked_mul(factor)
+            .and_then(|millis| total.checked_add(millis))
+            .ok_or_else(|| Some(ttl_too_large()))?;
+    }
+    Ok(total)
+}
+
+fn ttl_too_large() -> String {
+    "TTL is too large".to_string()
+}
+
+// =======================================================
+// 🧠 INFO: Binary Literals
+// =======================================================
+// Any argument can be sent as a length-prefixed binary literal instead of a
+// quoted string: SET("key",{5}) followed by a line break, then exactly 5 raw
+// bytes and another line break. Values that are not plain single-line UTF-8
+// are returned by GET in the same form (see `Reply::to_text`).
+
+/// Largest total size of the binary literals a single command may announce
+/// (512 MiB, the Redis limit for one bulk string).
+pub const MAX_LITERAL_SIZE: usize = 512 * 1024 * 1024;
+
+/// Lists the payload lengths of the binary literals announced by a command
+/// line, in the order their payloads are expected to follow it. Returns
+/// `None` if the line can't be parsed but looks like it announces a literal:
+/// its payload can't be told apart from the next command line.
+pub fn literal_lengths(input: &str) -> Option<Vec<usize>> {
+    match lexer::parse(input) {
+        Ok(statement) => Some(
+            statement
+                .args
+                .iter()
+                .filter_map(|arg| match arg {
+                    Arg::Literal(len) => Some(*len),
+                    Arg::Bytes(_) => None,
+                })
+                .collect(),
+        ),
+        Err(_) if announces_literal(input) => None,
+        Err(_) => Some(Vec::new()),
+    }
+}
+
+/// Whether `input` contains a literal header ({N}) anywhere, even inside
+/// what might be a quoted string.
+fn announces_literal(input: &str) -> bool {
+    input.split('{').skip(1).any(|rest| {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        digits > 0 && rest[digits..].starts_with('}')
+    })
+}
+
+/// Converts a key argument to a string; keys must be valid UTF-8.
+pub fn key_arg(arg: Vec<u8>) -> Result<String, Reply> {
+    String::from_utf8(arg).map_err(|_| Reply::error(ErrorCode::InvalidArgument, "Error: Keys must be valid UTF-8"))
+}
 
-    let num = num_part.parse::<u64>().map_err(|_| "Invalid TTL number".to_string())?;
+/// Reply for commands that need a selected database.
+pub fn no_database() -> Reply {
+    Reply::error(ErrorCode::NoDatabase, "No database selected")
+}
 
-    match unit_part.as_str() {
-        "s" => Ok(Duration::from_secs(num)),
-        "m" => Ok(Duration::from_secs(num * 60)),  // Convert minutes to seconds
-        "d" => Ok(Duration::from_secs(num * 60 * 60 * 24)),  // Convert days to seconds
-        _ => Err("Invalid TTL unit (use s, m, or d)".to_string()),
+/// Reply for a missing key.
+pub fn key_not_found(key: &str) -> Reply {
+    Reply::error(ErrorCode::KeyNotFound, format!("Error: Key \"{}\" not found", key))
+}
+
+/// Reply for a command applied to a key holding another type.
+pub fn wrong_type() -> Reply {
+    Reply::error(ErrorCode::WrongType, "Error: Operation against a key holding the wrong kind of value")
+}
+
+/// Reply carrying a whole value of any type.
+fn value_reply(value: &Value) -> Reply {
+    match value {
+        Value::String(bytes) => Reply::Value(bytes.clone()),
+        Value::List(items) => Reply::Array(items.iter().cloned().map(Reply::Value).collect()),
+        Value::Hash(fields) => Reply::Map(
+            fields
+                .iter()
+                .map(|(field, value)| (Reply::Value(field.clone().into_bytes()), Reply::Value(value.clone())))
+                .collect(),
+        ),
+        Value::Set(members) => Reply::Array(members.iter().cloned().map(Reply::Value).collect()),
+        Value::SortedSet(set) => Reply::Map(
+            set.iter()
+                .map(|(member, score)| (Reply::Value(member.to_vec()), zset::score_reply(score)))
+                .collect(),
+        ),
+        Value::Json(doc) => json::json_reply(doc),
     }
 }
 
+/// Reply for a malformed command.
+pub fn usage(text: &str) -> Reply {
+    Reply::error(ErrorCode::Syntax, format!("Usage: {}", text))
+}
+
 // =======================================================
 // 🧠 INFO: Main Command Parser
 // =======================================================
 /// Parses and executes database commands
 /// Supported commands:
-/// - SET("key","value",["ttl"]) - Stores key-value pair with optional TTL
+/// - SET("key","value",["ttl"],[NX|XX],[GET],[KEEPTTL],[VERSION,"n"]) - Stores key-value pair with optional TTL and conditions
+/// - SETNX("key","value") / GETSET("key","value") - Set if missing / set and return the old value
+/// - CAS("key","expected","new",["ttl"]) - Replaces a value only if it matches `expected`
+///   (or, with CAS("key",VERSION,"n","new",["ttl"]), only if the key is at version `n`)
 /// - GET("key") - Retrieves value for key
-/// - DEL("key") - Deletes key
-pub fn parse_statement(input: &str,  current_db_instance: &Option<Arc<DbInstance>> ) -> String {
-    let input = input.trim();
-
-    // Handle SET command
-    if input.starts_with("SET(") && input.ends_with(')') {
-        let content = &input[4..input.len() - 1];  // Extract content between parentheses
-        let args: Vec<&str> = content
-            .split(',')
-            .map(|s| s.trim().trim_matches('"'))  // Clean each argument
-            .collect();
-
-        if args.len() < 2 {
-            return "Usage: SET(\"key\",\"value\",[\"5s|5m|5d\"])".to_string();
-        }
-
-        let key = args[0].to_string();
-        let value = args[1].to_string();
-        let mut ttl: Option<Duration> = None;
-
-        // Parse TTL if provided
-        if args.len() == 3 {
-            ttl = match parse_duration(args[2]) {
-                Ok(dur) => Some(dur),
-                Err(e) => return e,  // Return error message if TTL parsing fails
+/// - DEL("key",[VERSION,"n"]) - Deletes key, optionally only at a given version
+/// - GETMETA("key") - Retrieves value, version, modification and expiry time
+/// - PREFIX("prefix",...) / RANGE("start","end",...) - Lists keys in order,
+///   optionally limited, reversed and with their values
+/// - KEYS("pattern") - Lists keys matching a glob pattern
+/// - SCAN("cursor",[MATCH,"pattern"],[COUNT,"n"]) - Iterates over keys in chunks
+/// - EXISTS("key",...) / TYPE("key") / DBSIZE() - Inspects the keyspace
+/// - RENAME("key","newkey") / RENAMENX(...) / COPY("key","newkey",[DB,"name"],[REPLACE])
+/// - FLUSHDB() - Deletes every key, keeping the database and its credentials
+/// - EXPIRE("key","seconds") - Sets or replaces the TTL of an existing key
+/// - EXPIREAT("key","unix-seconds") - Expires an existing key at an absolute time
+/// - PERSIST("key") - Removes the TTL of a key
+/// - MGET("key",...) / MSET("key","value",...) / MSETNX(...) / MDEL("key",...) -
+///   Multi-key variants, each applied under a single lock
+/// - INCR("key") / DECR("key") / INCRBY("key","n") / DECRBY("key","n") /
+///   INCRBYFLOAT("key","x") - Atomic arithmetic on numeric values, keeping the TTL
+/// - LPUSH/RPUSH("key","value",...) / LPOP/RPOP("key",["count"]) /
+///   LRANGE("key","start","stop") / LLEN("key") / LTRIM("key","start","stop") /
+///   LINDEX("key","index") - List operations (see list.rs)
+/// - HSET("key","field","value",...) / HGET / HMGET / HDEL / HGETALL /
+///   HINCRBY("key","field","n") / HEXISTS / HLEN - Hash operations (see hash.rs)
+/// - SADD("key","member",...) / SREM / SISMEMBER / SMEMBERS / SCARD /
+///   SUNION/SINTER/SDIFF("key",...) and their STORE variants - Set operations (see set.rs)
+/// - ZADD("key","score","member",...) / ZREM / ZSCORE / ZINCRBY / ZRANGE / ZREVRANGE /
+///   ZRANGEBYSCORE / ZRANK - Sorted set operations (see zset.rs)
+/// - JSON.SET("key","path","json") / JSON.GET("key",["path"]) / JSON.DEL /
+///   JSON.NUMINCRBY / JSON.ARRAPPEND - JSON document operations (see json.rs)
+/// - IDX.CREATE("name","path") / IDX.DROP / IDX.LIST() / IDX.FIND("name","value") /
+///   IDX.RANGE("name","min","max",...) - Secondary indexes over hash and JSON
+///   fields, kept up to date by every write (see index.rs)
+/// - TTL("key") / PTTL("key") - Remaining TTL in seconds / milliseconds (-1 if none)
+///
+/// `literals` holds the payloads announced by `literal_lengths`, in order.
+pub fn parse_statement(
+    input: &str,
+    literals: Vec<Vec<u8>>,
+    current_db_instance: &Option<Arc<DbInstance>>,
+    db_map: &DbMap,
+) -> Reply {
+    let statement = match lexer::parse(input) {
+        Ok(statement) => statement,
+        Err(e) => return Reply::error(ErrorCode::Syntax, format!("Error: {}", e)),
+    };
+
+    // Substitute the payloads of binary literals
+    let mut literals = literals.into_iter();
+    let mut args = Vec::with_capacity(statement.args.len());
+    for arg in statement.args {
+        match arg {
+            Arg::Bytes(bytes) => args.push(bytes),
+            Arg::Literal(_) => match literals.next() {
+                Some(payload) => args.push(payload),
+                None => return Reply::error(ErrorCode::Syntax, "Error: Missing binary literal payload"),
+            },
+        }
+    }
+
+    execute(&statement.command, args, current_db_instance, db_map)
+}
+
+/// Executes a command with fully decoded arguments. Command names are
+/// case-insensitive. `db_map` is only used by commands that reach into other
+/// databases.
+pub fn execute(command: &str, args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>, db_map: &DbMap) -> Reply {
+    match command.to_ascii_uppercase().as_str() {
+        "SET" => set(args, current_db_instance),
+        "SETNX" => setnx(args, current_db_instance),
+        "GETSET" => getset(args, current_db_instance),
+        "CAS" => cas(args, current_db_instance),
+        "GET" => get(args, current_db_instance),
+        "DEL" => del(args, current_db_instance),
+        "GETMETA" => get_meta(args, current_db_instance),
+        "PREFIX" => prefix_scan(args, current_db_instance),
+        "RANGE" => range_scan(args, current_db_instance),
+        "KEYS" => keys(args, current_db_instance),
+        "EXISTS" => exists(args, current_db_instance),
+        "RENAME" => rename(args, false, current_db_instance),
+        "RENAMENX" => rename(args, true, current_db_instance),
+        "COPY" => copy(args, current_db_instance, db_map),
+        "TYPE" => key_type(args, current_db_instance),
+        "DBSIZE" => db_size(args, current_db_instance),
+        "FLUSHDB" => flush_db(args, current_db_instance),
+        "SCAN" => scan(args, current_db_instance),
+        "EXPIRE" => expire(args, current_db_instance),
+        "EXPIREAT" => expire_at(args, current_db_instance),
+        "PERSIST" => persist(args, current_db_instance),
+        "MGET" => mget(args, current_db_instance),
+        "MSET" => mset(args, false, current_db_instance),
+        "MSETNX" => mset(args, true, current_db_instance),
+        "MDEL" => mdel(args, current_db_instance),
+        "INCR" => incr_by(args, Some(1), current_db_instance),
+        "DECR" => incr_by(args, Some(-1), current_db_instance),
+        "INCRBY" => incr_by(args, None, current_db_instance),
+        "DECRBY" => decr_by(args, current_db_instance),
+        "INCRBYFLOAT" => incr_by_float(args, current_db_instance),
+        "TTL" => ttl(args, false, current_db_instance),
+        "PTTL" => ttl(args, true, current_db_instance),
+        "LPUSH" => list::push(args, End::Left, current_db_instance),
+        "RPUSH" => list::push(args, End::Right, current_db_instance),
+        "LPOP" => list::pop(args, End::Left, current_db_instance),
+        "RPOP" => list::pop(args, End::Right, current_db_instance),
+        "LRANGE" => list::range(args, current_db_instance),
+        "LLEN" => list::len(args, current_db_instance),
+        "LTRIM" => list::trim(args, current_db_instance),
+        "LINDEX" => list::index(args, current_db_instance),
+        "HSET" => hash::set(args, current_db_instance),
+        "HGET" => hash::get(args, current_db_instance),
+        "HMGET" => hash::mget(args, current_db_instance),
+        "HDEL" => hash::del(args, current_db_instance),
+        "HGETALL" => hash::get_all(args, current_db_instance),
+        "HINCRBY" => hash::incr_by(args, current_db_instance),
+        "HEXISTS" => hash::exists(args, current_db_instance),
+        "HLEN" => hash::len(args, current_db_instance),
+        "SADD" => set::add(args, current_db_instance),
+        "SREM" => set::remove(args, current_db_instance),
+        "SISMEMBER" => set::is_member(args, current_db_instance),
+        "SMEMBERS" => set::members(args, current_db_instance),
+        "SCARD" => set::card(args, current_db_instance),
+        "SUNION" => set::combine(args, SetOp::Union, current_db_instance),
+        "SINTER" => set::combine(args, SetOp::Intersection, current_db_instance),
+        "SDIFF" => set::combine(args, SetOp::Difference, current_db_instance),
+        "SUNIONSTORE" => set::combine_store(args, SetOp::Union, current_db_instance),
+        "SINTERSTORE" => set::combine_store(args, SetOp::Intersection, current_db_instance),
+        "SDIFFSTORE" => set::combine_store(args, SetOp::Difference, current_db_instance),
+        "ZADD" => zset::add(args, current_db_instance),
+        "ZREM" => zset::remove(args, current_db_instance),
+        "ZSCORE" => zset::score(args, current_db_instance),
+        "ZINCRBY" => zset::incr_by(args, current_db_instance),
+        "ZRANGE" => zset::range(args, false, current_db_instance),
+        "ZREVRANGE" => zset::range(args, true, current_db_instance),
+        "ZRANGEBYSCORE" => zset::range_by_score(args, current_db_instance),
+        "ZRANK" => zset::rank(args, current_db_instance),
+        "JSON.SET" => json::set(args, current_db_instance),
+        "JSON.GET" => json::get(args, current_db_instance),
+        "JSON.DEL" => json::del(args, current_db_instance),
+        "JSON.NUMINCRBY" => json::num_incr_by(args, current_db_instance),
+        "JSON.ARRAPPEND" => json::arr_append(args, current_db_instance),
+        "IDX.CREATE" => index::create(args, current_db_instance),
+        "IDX.DROP" => index::drop_index(args, current_db_instance),
+        "IDX.LIST" => index::list(args, current_db_instance),
+        "IDX.FIND" => index::find(args, current_db_instance),
+        "IDX.RANGE" => index::range(args, current_db_instance),
+        _ => Reply::error(ErrorCode::UnknownCommand, "Unknown command"),  // Fallback for invalid commands
+    }
+}
+
+/// Which existing state a conditional SET requires.
+#[derive(Clone, Copy, PartialEq)]
+enum SetCondition {
+    // NX: the key must not exist.
+    IfMissing,
+    // XX: the key must exist.
+    IfExists,
+}
+
+/// SET("key","value",["ttl"],[NX|XX],[GET],[KEEPTTL],[VERSION,"n"])
+/// - NX / XX only set the key if it doesn't / does exist already; if the
+///   condition fails nothing is written and the reply is nil
+/// - GET replies with the previous value (nil if there was none) instead of OK
+/// - KEEPTTL keeps the existing TTL of the key
+/// - VERSION only sets the key if its current version is `n` (see GETMETA)
+fn set(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "SET(\"key\",\"value\",[\"ttl\"],[NX|XX],[GET],[KEEPTTL],[VERSION,\"n\"])";
+    if args.len() < 2 {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let value = args.next().unwrap();
+    let mut ttl: Option<Duration> = None;
+    let mut condition = None;
+    let mut return_old = false;
+    let mut keep_ttl = false;
+    let mut expected_version = None;
+
+    // Options are recognised by name; anything else is the TTL
+    while let Some(arg) = args.next() {
+        match arg.to_ascii_uppercase().as_slice() {
+            b"NX" | b"XX" if condition.is_some() => return usage(USAGE),
+            b"NX" => condition = Some(SetCondition::IfMissing),
+            b"XX" => condition = Some(SetCondition::IfExists),
+            b"GET" => return_old = true,
+            b"KEEPTTL" => keep_ttl = true,
+            b"VERSION" => match args.next().as_deref().and_then(parse_version) {
+                Some(version) => expected_version = Some(version),
+                None => return usage(USAGE),
+            },
+            _ if ttl.is_none() => {
+                ttl = match parse_duration(&String::from_utf8_lossy(&arg)) {
+                    Ok(dur) => Some(dur),
+                    Err(e) => return Reply::error(ErrorCode::InvalidArgument, e),  // Return error message if TTL parsing fails
+                };
+            }
+            _ => return usage(USAGE),
+        }
+    }
+    if keep_ttl && ttl.is_some() {
+        return Reply::error(ErrorCode::Syntax, "Error: KEEPTTL can't be combined with a TTL");
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let old = db.get(&key);
+            if let Some(expected) = expected_version
+                && let Err(e) = check_version(&key, old, expected)
+            {
+                return e;
+            }
+            let old_expiry = old.and_then(|entry| entry.expires_at);
+            let old_reply = match (return_old, old.map(|entry| &entry.value)) {
+                (false, _) => None,
+                (true, None) => Some(Reply::Nil),
+                (true, Some(Value::String(value))) => Some(Reply::Value(value.clone())),
+                (true, Some(_)) => return wrong_type(),
+            };
+
+            let allowed = match condition {
+                Some(SetCondition::IfMissing) => old.is_none(),
+                Some(SetCondition::IfExists) => old.is_some(),
+                None => true,
+            };
+            if !allowed {
+                return old_reply.unwrap_or(Reply::Nil);
+            }
+
+            let mut entry = ValueWithExpiry::new(Value::String(value), ttl);
+            if keep_ttl {
+                entry.expires_at = old_expiry;
+            }
+            // Log while holding the lock so the log order matches the applied order
+            db_instance.log_set(&key, &mut entry);
+            db.insert(key, entry);
+            old_reply.unwrap_or(Reply::Ok)
+        }
+        None => no_database(),
+    }
+}
+
+/// SETNX("key","value") - replies 1 if the key was set, 0 if it already existed
+fn setnx(mut args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("SETNX(\"key\",\"value\")");
+    }
+    args.push(b"NX".to_vec());
+    match set(args, current_db_instance) {
+        Reply::Ok => Reply::Int(1),
+        Reply::Nil => Reply::Int(0),
+        other => other,
+    }
+}
+
+/// GETSET("key","value") - sets the key (clearing its TTL) and replies with
+/// the previous value, nil if there was none
+fn getset(mut args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("GETSET(\"key\",\"value\")");
+    }
+    args.push(b"GET".to_vec());
+    set(args, current_db_instance)
+}
+
+/// What CAS compares the current entry of a key against.
+enum CasExpectation {
+    Value(Vec<u8>),
+    Version(u64),
+}
+
+/// CAS("key","expected","new",["ttl"]) - replaces the value only if it
+/// currently equals `expected`; replies 1 if it was swapped, 0 otherwise.
+/// CAS("key",VERSION,"n","new",["ttl"]) does the same if the key, of any
+/// type, is still at version `n`. The TTL is kept unless a new one is given.
+fn cas(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "CAS(\"key\",\"expected\",\"new\",[\"ttl\"]) or CAS(\"key\",VERSION,\"n\",\"new\",[\"ttl\"])";
+    let by_version = args.len() >= 4 && args[1].eq_ignore_ascii_case(b"VERSION");
+    if args.len() < 3 || args.len() > 4 + by_version as usize {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let expected = if by_version {
+        args.next();
+        match parse_version(&args.next().unwrap()) {
+            Some(version) => CasExpectation::Version(version),
+            None => return usage(USAGE),
+        }
+    } else {
+        CasExpectation::Value(args.next().unwrap())
+    };
+    let value = args.next().unwrap();
+    let ttl = match args.next().map(|raw_ttl| parse_duration(&String::from_utf8_lossy(&raw_ttl))) {
+        Some(Ok(dur)) => Some(dur),
+        Some(Err(e)) => return Reply::error(ErrorCode::InvalidArgument, e),
+        None => None,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let Some(old) = db.get(&key) else {
+                return key_not_found(&key);
+            };
+            let matches = match (&expected, &old.value) {
+                (CasExpectation::Version(version), _) => old.version == *version,
+                (CasExpectation::Value(expected), Value::String(current)) => current == expected,
+                (CasExpectation::Value(_), _) => return wrong_type(),
             };
+            if !matches {
+                return Reply::Int(0);
+            }
+
+            let mut entry = ValueWithExpiry::new(Value::String(value), ttl);
+            if ttl.is_none() {
+                entry.expires_at = old.expires_at;
+            }
+            db_instance.log_set(&key, &mut entry);
+            db.insert(key, entry);
+            Reply::Int(1)
         }
+        None => no_database(),
+    }
+}
 
-        let entry = ValueWithExpiry::new(value, ttl);
+/// GET("key")
+fn get(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("GET(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
 
-        match current_db_instance {
-            Some(db_instance) => {
-                {
-                    let mut db = db_instance.data.lock().unwrap();
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match db.get(&key) {
+                Some(val) if !val.is_expired() => match &val.value {
+                    Value::String(value) => Reply::Value(value.clone()),
+                    _ => wrong_type(),
+                },
+                Some(_) => {
+                    db.remove(&key);
+                    db_instance.log(LogRecord::Expire { key: key.clone() });
+                    Reply::error(
+                        ErrorCode::KeyNotFound,
+                        format!("Error: Key \"{}\" has expired and is deleted", key),
+                    )
+                }
+                None => key_not_found(&key),
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// DEL("key",[VERSION,"n"]) - with VERSION, only deletes the key if its
+/// current version is `n`
+fn del(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "DEL(\"key\",[VERSION,\"n\"])";
+    if args.len() != 1 && args.len() != 3 {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let expected_version = match (args.next(), args.next()) {
+        (Some(option), Some(version)) if option.eq_ignore_ascii_case(b"VERSION") => match parse_version(&version) {
+            Some(version) => Some(version),
+            None => return usage(USAGE),
+        },
+        (None, None) => None,
+        _ => return usage(USAGE),
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            // An expired key is already gone, as for GET
+            purge_if_expired(&mut db, db_instance, &key);
+            if let Some(expected) = expected_version
+                && let Err(e) = check_version(&key, db.get(&key), expected)
+            {
+                return e;
+            }
+
+            // Log only if key was actually removed
+            if db.remove(&key).is_some() {
+                db_instance.log(LogRecord::Del { key });
+                Reply::Ok
+            } else {
+                key_not_found(&key)
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// GETMETA("key") - replies with the value, version, last modification time
+/// (Unix ms, 0 if unknown) and expiry time (Unix ms, nil if none) of a key
+fn get_meta(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("GETMETA(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            match db.get(&key) {
+                Some(entry) => {
+                    let expires_at = match entry.expires_at {
+                        Some(deadline) => Reply::Int(db::instant_to_epoch_ms(deadline) as i64),
+                        None => Reply::Nil,
+                    };
+                    Reply::Map(vec![
+                        (Reply::Value(b"type".to_vec()), Reply::Value(entry.value.type_name().as_bytes().to_vec())),
+                        (Reply::Value(b"value".to_vec()), value_reply(&entry.value)),
+                        (Reply::Value(b"version".to_vec()), Reply::Int(entry.version as i64)),
+                        (Reply::Value(b"modified_at".to_vec()), Reply::Int(entry.modified_at as i64)),
+                        (Reply::Value(b"expires_at".to_vec()), expires_at),
+                    ])
+                }
+                None => key_not_found(&key),
+            }
+        }
+        None => no_database(),
+    }
+}
+
+fn parse_version(value: &[u8]) -> Option<u64> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Checks that `entry`, the current entry of `key`, has the expected version.
+fn check_version(key: &str, entry: Option<&ValueWithExpiry>, expected: u64) -> Result<(), Reply> {
+    match entry {
+        Some(entry) if entry.version == expected => Ok(()),
+        Some(entry) => Err(Reply::error(
+            ErrorCode::VersionMismatch,
+            format!("Error: Key \"{}\" is at version {}, not {}", key, entry.version, expected),
+        )),
+        None => Err(key_not_found(key)),
+    }
+}
+
+/// Converts every argument of a multi-key command to a key, failing on the
+/// first invalid one.
+fn key_args(args: Vec<Vec<u8>>) -> Result<Vec<String>, Reply> {
+    args.into_iter().map(key_arg).collect()
+}
+
+/// MGET("key",...) - replies with the values in order, nil for missing keys
+fn mget(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() {
+        return usage("MGET(\"key\",...)");
+    }
+    let keys = match key_args(args) {
+        Ok(keys) => keys,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let values = keys
+                .iter()
+                .map(|key| {
+                    purge_if_expired(&mut db, db_instance, key);
+                    match db.get(key).map(|entry| &entry.value) {
+                        Some(Value::String(value)) => Reply::Value(value.clone()),
+                        // Missing keys and other types read as nil
+                        _ => Reply::Nil,
+                    }
+                })
+                .collect();
+            Reply::Array(values)
+        }
+        None => no_database(),
+    }
+}
+
+/// MSET("key","value",...) - sets every pair, or none if any argument is invalid
+/// MSETNX("key","value",...) - likewise, but only if none of the keys exist;
+/// replies 1 if the keys were set, 0 otherwise
+fn mset(args: Vec<Vec<u8>>, only_new: bool, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return usage(if only_new { "MSETNX(\"key\",\"value\",...)" } else { "MSET(\"key\",\"value\",...)" });
+    }
+
+    // Validate everything before touching the database
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    let mut args = args.into_iter();
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        match key_arg(key) {
+            Ok(key) => pairs.push((key, value)),
+            Err(e) => return e,
+        }
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            if only_new {
+                for (key, _) in &pairs {
+                    purge_if_expired(&mut db, db_instance, key);
+                }
+                if pairs.iter().any(|(key, _)| db.contains_key(key)) {
+                    return Reply::Int(0);
+                }
+            }
+
+            let mut records = Vec::with_capacity(pairs.len());
+            for (key, value) in pairs {
+                let mut entry = ValueWithExpiry::new(Value::String(value), None);
+                db_instance.stamp(&mut entry);
+                records.push(LogRecord::Set { key: key.clone(), entry: entry.to_serializable() });
+                db.insert(key, entry);
+            }
+            db_instance.log_batch(records);
+
+            if only_new { Reply::Int(1) } else { Reply::Ok }
+        }
+        None => no_database(),
+    }
+}
+
+/// MDEL("key",...) - replies with the number of keys deleted
+fn mdel(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() {
+        return usage("MDEL(\"key\",...)");
+    }
+    let keys = match key_args(args) {
+        Ok(keys) => keys,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let mut records = Vec::new();
+            for key in keys {
+                purge_if_expired(&mut db, db_instance, &key);
+                if db.remove(&key).is_some() {
+                    records.push(LogRecord::Del { key });
+                }
+            }
+            let deleted = records.len();
+            db_instance.log_batch(records);
+            Reply::Int(deleted as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// INCR("key") / DECR("key") when `delta` is given, otherwise INCRBY("key","n").
+/// Missing keys count as 0; replies with the new value.
+fn incr_by(args: Vec<Vec<u8>>, delta: Option<i64>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    let expected = if delta.is_some() { 1 } else { 2 };
+    if args.len() != expected {
+        return match delta {
+            Some(1) => usage("INCR(\"key\")"),
+            Some(_) => usage("DECR(\"key\")"),
+            None => usage("INCRBY(\"key\",\"increment\")"),
+        };
+    }
+    let mut args = args.into_iter();
+
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let delta = match delta {
+        Some(delta) => delta,
+        None => match parse_integer(&args.next().unwrap()) {
+            Some(delta) => delta,
+            None => return Reply::error(ErrorCode::InvalidArgument, "Error: Increment must be an integer"),
+        },
+    };
+
+    add_integer(key, delta, current_db_instance)
+}
+
+/// DECRBY("key","n")
+fn decr_by(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("DECRBY(\"key\",\"decrement\")");
+    }
+    let mut args = args.into_iter();
+
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let Some(decrement) = parse_integer(&args.next().unwrap()) else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Decrement must be an integer");
+    };
+    let Some(delta) = decrement.checked_neg() else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Increment would overflow");
+    };
+
+    add_integer(key, delta, current_db_instance)
+}
+
+/// Adds `delta` to the integer stored at `key`.
+fn add_integer(key: String, delta: i64, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    update_value(key, current_db_instance, |current| {
+        let current = match current {
+            Some(value) => parse_integer(value)
+                .ok_or_else(|| Reply::error(ErrorCode::InvalidArgument, "Error: Value is not an integer"))?,
+            None => 0,
+        };
+        let new = current
+            .checked_add(delta)
+            .ok_or_else(|| Reply::error(ErrorCode::InvalidArgument, "Error: Increment would overflow"))?;
+        Ok((new.to_string().into_bytes(), Reply::Int(new)))
+    })
+}
+
+/// INCRBYFLOAT("key","x") - missing keys count as 0; replies with the new value
+fn incr_by_float(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("INCRBYFLOAT(\"key\",\"increment\")");
+    }
+    let mut args = args.into_iter();
+
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let Some(delta) = parse_float(&args.next().unwrap()) else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Increment must be a number");
+    };
+
+    update_value(key, current_db_instance, |current| {
+        let current = match current {
+            Some(value) => parse_float(value)
+                .ok_or_else(|| Reply::error(ErrorCode::InvalidArgument, "Error: Value is not a number"))?,
+            None => 0.0,
+        };
+        let new = current + delta;
+        if !new.is_finite() {
+            return Err(Reply::error(ErrorCode::InvalidArgument, "Error: Increment would overflow"));
+        }
+        let new = new.to_string().into_bytes();
+        Ok((new.clone(), Reply::Value(new)))
+    })
+}
+
+pub fn parse_integer(value: &[u8]) -> Option<i64> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Parses a finite float (no "inf" or "NaN").
+pub fn parse_float(value: &[u8]) -> Option<f64> {
+    std::str::from_utf8(value).ok()?.parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// Replaces the value of `key` with the one computed by `update` from the
+/// current value (`None` if the key is missing), keeping its TTL. `update`
+/// returns the new value and the reply to send, or an error reply to leave
+/// the key untouched.
+fn update_value(
+    key: String,
+    current_db_instance: &Option<Arc<DbInstance>>,
+    update: impl FnOnce(Option<&[u8]>) -> Result<(Vec<u8>, Reply), Reply>,
+) -> Reply {
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let current = match db.get(&key).map(|entry| &entry.value) {
+                Some(Value::String(value)) => Some(value.as_slice()),
+                Some(_) => return wrong_type(),
+                None => None,
+            };
+            let (value, reply) = match update(current) {
+                Ok(result) => result,
+                Err(e) => return e,
+            };
+            match db.get_mut(&key) {
+                Some(entry) => {
+                    entry.value = Value::String(value);
+                    db_instance.log_set(&key, entry);
+                }
+                None => {
+                    let mut entry = ValueWithExpiry::new(Value::String(value), None);
+                    db_instance.log_set(&key, &mut entry);
                     db.insert(key, entry);
                 }
-                
-                // Persist after releasing the lock
-                db_instance.persist();
-                "OK".to_string()
-            }
-            None => "No database selected".to_string(),
-        }
-    } 
-    // Handle GET command
-    else if input.starts_with("GET(") && input.ends_with(')') {
-        let content = &input[4..input.len() - 1];
-        let key = content.trim().trim_matches('"');
-
-        match current_db_instance {
-            Some(db_instance) => {
-                let mut db = db_instance.data.lock().unwrap();
-                match db.get(key) {
-                    Some(val) if !val.is_expired() => val.value.clone(),
+            }
+            reply
+        }
+        None => no_database(),
+    }
+}
+
+/// Options shared by the ordered scan commands.
+struct ScanOptions {
+    // Maximum number of keys to return.
+    limit: Option<usize>,
+    // Walk the keys from last to first.
+    reverse: bool,
+    // Return key/value pairs instead of just keys.
+    with_values: bool,
+}
+
+/// Parses the trailing [limit],[REV],[WITHVALUES] arguments of a scan command.
+fn scan_options(args: impl Iterator<Item = Vec<u8>>, usage_text: &str) -> Result<ScanOptions, Reply> {
+    let mut options = ScanOptions { limit: None, reverse: false, with_values: false };
+    for arg in args {
+        match arg.to_ascii_uppercase().as_slice() {
+            b"REV" => options.reverse = true,
+            b"WITHVALUES" => options.with_values = true,
+            _ if options.limit.is_none() => match std::str::from_utf8(&arg).ok().and_then(|n| n.parse().ok()) {
+                Some(limit) => options.limit = Some(limit),
+                None => return Err(usage(usage_text)),
+            },
+            _ => return Err(usage(usage_text)),
+        }
+    }
+    Ok(options)
+}
+
+/// Collects the live keys between `start` and `end` in order (or reverse
+/// order), applying the limit of `options`.
+fn scan_range(db: &Keyspace, start: Bound<&str>, end: Bound<&str>, options: &ScanOptions) -> Reply {
+    let range = db
+        .range::<str, _>((start, end))
+        .filter(|(_, entry)| !entry.is_expired());
+    let limit = options.limit.unwrap_or(usize::MAX);
+    let entries: Vec<_> = if options.reverse {
+        range.rev().take(limit).collect()
+    } else {
+        range.take(limit).collect()
+    };
+
+    if options.with_values {
+        Reply::Map(
+            entries
+                .into_iter()
+                .map(|(key, entry)| (Reply::Value(key.clone().into_bytes()), value_reply(&entry.value)))
+                .collect(),
+        )
+    } else {
+        Reply::Array(entries.into_iter().map(|(key, _)| Reply::Value(key.clone().into_bytes())).collect())
+    }
+}
+
+/// Smallest string greater than every string starting with `prefix`, or
+/// `None` if there is none (the prefix consists of `char::MAX` only).
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut end = prefix.to_string();
+    while let Some(last) = end.pop() {
+        // Skip the surrogate gap, which has no chars
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            end.push(next);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// PREFIX("prefix",["limit"],[REV],[WITHVALUES]) - keys starting with
+/// `prefix`, in lexicographic order
+fn prefix_scan(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "PREFIX(\"prefix\",[\"limit\"],[REV],[WITHVALUES])";
+    if args.is_empty() {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let prefix = match key_arg(args.next().unwrap()) {
+        Ok(prefix) => prefix,
+        Err(e) => return e,
+    };
+    let options = match scan_options(args, USAGE) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            let end = prefix_end(&prefix);
+            let end = match &end {
+                Some(end) => Bound::Excluded(end.as_str()),
+                None => Bound::Unbounded,
+            };
+            scan_range(&db, Bound::Included(&prefix), end, &options)
+        }
+        None => no_database(),
+    }
+}
+
+/// RANGE("start","end",["limit"],[REV],[WITHVALUES]) - keys from `start`
+/// (inclusive) to `end` (exclusive) in lexicographic order; an empty bound
+/// is open
+fn range_scan(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "RANGE(\"start\",\"end\",[\"limit\"],[REV],[WITHVALUES])";
+    if args.len() < 2 {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let (start, end) = match (key_arg(args.next().unwrap()), key_arg(args.next().unwrap())) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    let options = match scan_options(args, USAGE) {
+        Ok(options) => options,
+        Err(e) => return e,
+    };
+    if !start.is_empty() && !end.is_empty() && start > end {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Range start is after its end");
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            let start = if start.is_empty() { Bound::Unbounded } else { Bound::Included(start.as_str()) };
+            let end = if end.is_empty() { Bound::Unbounded } else { Bound::Excluded(end.as_str()) };
+            scan_range(&db, start, end, &options)
+        }
+        None => no_database(),
+    }
+}
+
+/// KEYS("pattern") - every live key matching a glob pattern, in order.
+/// Walks the whole keyspace under one lock, so it is meant for debugging;
+/// use SCAN on large databases.
+fn keys(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("KEYS(\"pattern\")");
+    }
+    let pattern = String::from_utf8_lossy(&args[0]).into_owned();
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            let keys = db
+                .iter()
+                .filter(|(key, entry)| !entry.is_expired() && glob::matches(&pattern, key))
+                .map(|(key, _)| Reply::Value(key.clone().into_bytes()))
+                .collect();
+            Reply::Array(keys)
+        }
+        None => no_database(),
+    }
+}
+
+/// Number of keys SCAN examines per call unless COUNT says otherwise.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// SCAN("cursor",[MATCH,"pattern"],[COUNT,"n"]) - iterates over the keyspace
+/// in chunks. Start with cursor "0"; each call examines up to COUNT keys
+/// after the cursor and replies with the next cursor followed by the
+/// matching keys. Iteration is complete when the next cursor is "0".
+///
+/// The cursor encodes the last key examined (as hex), so the lock is only
+/// held for one chunk, and every key that exists for the whole iteration is
+/// returned exactly once.
+fn scan(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "SCAN(\"cursor\",[MATCH,\"pattern\"],[COUNT,\"n\"])";
+    if args.is_empty() {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let after = match args.next().unwrap().as_slice() {
+        b"0" => None,
+        cursor => match decode_cursor(cursor) {
+            Some(key) => Some(key),
+            None => return Reply::error(ErrorCode::InvalidArgument, "Error: Invalid cursor"),
+        },
+    };
+
+    let mut pattern = None;
+    let mut count = DEFAULT_SCAN_COUNT;
+    while let Some(option) = args.next() {
+        let value = match args.next() {
+            Some(value) => String::from_utf8_lossy(&value).into_owned(),
+            None => return usage(USAGE),
+        };
+        match option.to_ascii_uppercase().as_slice() {
+            b"MATCH" => pattern = Some(value),
+            b"COUNT" => match value.parse::<usize>() {
+                Ok(n) if n > 0 => count = n,
+                _ => return Reply::error(ErrorCode::InvalidArgument, "Error: COUNT must be a positive integer"),
+            },
+            _ => return usage(USAGE),
+        }
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            let start = match &after {
+                Some(key) => Bound::Excluded(key.as_str()),
+                None => Bound::Unbounded,
+            };
+
+            let mut last = None;
+            let mut keys = Vec::new();
+            for (key, entry) in db.range::<str, _>((start, Bound::Unbounded)).take(count) {
+                last = Some(key);
+                if !entry.is_expired() && pattern.as_ref().is_none_or(|pattern| glob::matches(pattern, key)) {
+                    keys.push(Reply::Value(key.clone().into_bytes()));
+                }
+            }
+
+            // Iteration is over once no keys are left after the last one examined
+            let more = last.is_some_and(|key| {
+                db.range::<str, _>((Bound::Excluded(key.as_str()), Bound::Unbounded))
+                    .next()
+                    .is_some()
+            });
+            let next = match last {
+                Some(key) if more => encode_cursor(key),
+                _ => "0".to_string(),
+            };
+            Reply::Array(vec![Reply::Value(next.into_bytes()), Reply::Array(keys)])
+        }
+        None => no_database(),
+    }
+}
+
+/// Encodes a key as a SCAN cursor. Hex strings have an even length, so they
+/// never collide with the "0" start/end cursor.
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_cursor(cursor: &[u8]) -> Option<String> {
+    // from_str_radix alone would also accept a sign, as in "+f"
+    if !cursor.len().is_multiple_of(2) || !cursor.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let bytes = cursor
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// EXISTS("key",...) - replies with how many of the keys exist (a key
+/// given twice counts twice)
+fn exists(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() {
+        return usage("EXISTS(\"key\",...)");
+    }
+    let keys = match key_args(args) {
+        Ok(keys) => keys,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            let count = keys
+                .iter()
+                .filter(|key| db.get(key.as_str()).is_some_and(|entry| !entry.is_expired()))
+                .count();
+            Reply::Int(count as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// RENAME("key","newkey") - moves a key (with its TTL), replacing `newkey`
+/// RENAMENX("key","newkey") - likewise, but only if `newkey` doesn't exist;
+/// replies 1 if the key was renamed, 0 otherwise
+fn rename(args: Vec<Vec<u8>>, only_new: bool, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage(if only_new { "RENAMENX(\"key\",\"newkey\")" } else { "RENAME(\"key\",\"newkey\")" });
+    }
+    let (key, new_key) = match key_args(args) {
+        Ok(mut keys) => (keys.remove(0), keys.remove(0)),
+        Err(e) => return e,
+    };
+    let done = if only_new { Reply::Int(1) } else { Reply::Ok };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            purge_if_expired(&mut db, db_instance, &new_key);
+            if !db.contains_key(&key) {
+                return key_not_found(&key);
+            }
+            if only_new && db.contains_key(&new_key) {
+                return Reply::Int(0);
+            }
+            if key == new_key {
+                return done;
+            }
+
+            let mut entry = db.remove(&key).unwrap();
+            db_instance.stamp(&mut entry);
+            db_instance.log_batch(vec![
+                LogRecord::Del { key },
+                LogRecord::Set { key: new_key.clone(), entry: entry.to_serializable() },
+            ]);
+            db.insert(new_key, entry);
+            done
+        }
+        None => no_database(),
+    }
+}
+
+/// COPY("key","newkey",[DB,"name"],[REPLACE]) - copies a key (with its TTL),
+/// optionally into another database; replies 1 if it was copied, 0 if
+/// `newkey` exists and REPLACE wasn't given. Databases that require
+/// authentication can't be copied into.
+fn copy(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>, db_map: &DbMap) -> Reply {
+    const USAGE: &str = "COPY(\"key\",\"newkey\",[DB,\"name\"],[REPLACE])";
+    if args.len() < 2 {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let (key, new_key) = match (key_arg(args.next().unwrap()), key_arg(args.next().unwrap())) {
+        (Ok(key), Ok(new_key)) => (key, new_key),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    let mut target_name = None;
+    let mut replace = false;
+    while let Some(option) = args.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"DB" => match args.next() {
+                Some(name) => target_name = Some(String::from_utf8_lossy(&name).into_owned()),
+                None => return usage(USAGE),
+            },
+            b"REPLACE" => replace = true,
+            _ => return usage(USAGE),
+        }
+    }
+
+    let Some(db_instance) = current_db_instance else {
+        return no_database();
+    };
+
+    // Take the source entry first; the two databases are never locked together
+    let entry = {
+        let mut db = db_instance.data.lock().unwrap();
+        purge_if_expired(&mut db, db_instance, &key);
+        match db.get(&key) {
+            Some(entry) => entry.clone(),
+            None => return key_not_found(&key),
+        }
+    };
+
+    let target = match target_name {
+        Some(name) if name != db_instance.name => match db::get_or_load(db_map, &name) {
+            Some(target) if target.require_auth => {
+                return Reply::error(
+                    ErrorCode::AuthRequired,
+                    format!("Error: Database '{}' requires authentication", name),
+                );
+            }
+            Some(target) => target,
+            None => return Reply::error(ErrorCode::DbNotFound, format!("Database '{}' not found", name)),
+        },
+        _ => DbInstance::clone(db_instance),
+    };
+
+    let mut db = target.data.lock().unwrap();
+    purge_if_expired(&mut db, &target, &new_key);
+    if db.contains_key(&new_key) && !replace {
+        return Reply::Int(0);
+    }
+    let mut entry = entry;
+    target.log_set(&new_key, &mut entry);
+    db.insert(new_key, entry);
+    Reply::Int(1)
+}
+
+/// TYPE("key") - the type of the value stored at a key, "none" if it doesn't exist
+fn key_type(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("TYPE(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            match db.get(&key) {
+                Some(entry) if !entry.is_expired() => Reply::Value(entry.value.type_name().as_bytes().to_vec()),
+                _ => Reply::Value(b"none".to_vec()),
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// DBSIZE() - number of keys in the database
+fn db_size(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if !args.is_empty() {
+        return usage("DBSIZE()");
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let db = db_instance.data.lock().unwrap();
+            Reply::Int(db.values().filter(|entry| !entry.is_expired()).count() as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// FLUSHDB() - deletes every key; the database and its credentials remain
+fn flush_db(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if !args.is_empty() {
+        return usage("FLUSHDB()");
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            db.clear();
+            db_instance.log(LogRecord::Clear);
+            Reply::Ok
+        }
+        None => no_database(),
+    }
+}
+
+/// EXPIRE("key","ttl")
+fn expire(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("EXPIRE(\"key\",\"ttl\")");
+    }
+    let mut args = args.into_iter();
+
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    // A bare number is seconds, as in Redis; otherwise any TTL SET accepts
+    let ttl = String::from_utf8_lossy(&args.next().unwrap()).into_owned();
+    let ttl = if !ttl.is_empty() && ttl.bytes().all(|b| b.is_ascii_digit()) {
+        parse_duration(&format!("{}s", ttl))
+    } else {
+        parse_duration(&ttl)
+    };
+    let deadline = match ttl {
+        Ok(ttl) => Instant::now().checked_add(ttl),
+        Err(e) => return Reply::error(ErrorCode::InvalidArgument, format!("Error: {}", e)),
+    };
+    let Some(deadline) = deadline else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: TTL is too large");
+    };
+
+    set_deadline(key, deadline, current_db_instance)
+}
+
+/// EXPIREAT("key","unix-seconds")
+fn expire_at(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("EXPIREAT(\"key\",\"unix-seconds\")");
+    }
+    let mut args = args.into_iter();
+
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let timestamp_ms = match String::from_utf8_lossy(&args.next().unwrap()).parse::<u64>() {
+        Ok(seconds) => seconds.checked_mul(1000),
+        Err(_) => return Reply::error(ErrorCode::InvalidArgument, "Error: Timestamp must be a non-negative integer"),
+    };
+    // Convert to a monotonic deadline, rejecting timestamps too far out to represent
+    let deadline = timestamp_ms.and_then(|ms| {
+        Instant::now().checked_add(Duration::from_millis(ms.saturating_sub(db::now_epoch_ms())))
+    });
+    let Some(deadline) = deadline else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Timestamp is too large");
+    };
+
+    set_deadline(key, deadline, current_db_instance)
+}
+
+/// Sets the expiry of an existing key. Deadlines that have already passed
+/// delete the key straight away.
+fn set_deadline(key: String, deadline: Instant, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            if deadline <= Instant::now() {
+                return match db.remove(&key) {
                     Some(_) => {
-                        db.remove(key);
-                        drop(db); 
-                        db_instance.persist();
-                        format!("Error: Key \"{}\" has expired and is deleted", key)
+                        db_instance.log(LogRecord::Del { key });
+                        Reply::Ok
                     }
-                    None => format!("Error: Key \"{}\" not found", key),
+                    None => key_not_found(&key),
+                };
+            }
+            match db.get_mut(&key) {
+                Some(entry) => {
+                    entry.expires_at = Some(deadline);
+                    db_instance.log_set(&key, entry);
+                    Reply::Ok
                 }
+                None => key_not_found(&key),
             }
-            None => "No database selected".to_string(),
         }
-    } 
-    // Handle DEL command
-    else if input.starts_with("DEL(") && input.ends_with(')') {
-        let content = &input[4..input.len() - 1];
-        let key = content.trim().trim_matches('"');
+        None => no_database(),
+    }
+}
 
-        match current_db_instance {
-            Some(db_instance) => {
-                let removed = {
-                    let mut db = db_instance.data.lock().unwrap();
-                    db.remove(key).is_some()
-                };
-                
-                // Persist only if key was actually removed
-                if removed {
-                    db_instance.persist();
-                    "OK".to_string()
-                } else {
-                    format!("Error: Key \"{}\" not found", key)
+/// PERSIST("key") - replies 1 if a TTL was removed, 0 if the key had none
+fn persist(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("PERSIST(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            match db.get_mut(&key) {
+                Some(entry) if entry.expires_at.is_some() => {
+                    entry.expires_at = None;
+                    db_instance.log_set(&key, entry);
+                    Reply::Int(1)
                 }
+                Some(_) => Reply::Int(0),
+                None => key_not_found(&key),
             }
-            None => "No database selected".to_string(),
         }
+        None => no_database(),
+    }
+}
+
+/// TTL("key") / PTTL("key") - remaining time to live in seconds (rounded)
+/// or milliseconds; -1 if the key has no expiry
+fn ttl(args: Vec<Vec<u8>>, millis: bool, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage(if millis { "PTTL(\"key\")" } else { "TTL(\"key\")" });
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            match db.get(&key) {
+                Some(entry) => match entry.expires_at {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now()).as_millis();
+                        let remaining = if millis { remaining } else { (remaining + 500) / 1000 };
+                        Reply::Int(i64::try_from(remaining).unwrap_or(i64::MAX))
+                    }
+                    None => Reply::Int(-1),
+                },
+                None => key_not_found(&key),
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// Removes `key` if it has expired, logging the expiry.
+pub fn purge_if_expired(db: &mut Keyspace, db_instance: &DbInstance, key: &str) {
+    if db.get(key).is_some_and(ValueWithExpiry::is_expired) {
+        db.remove(key);
+        db_instance.log(LogRecord::Expire { key: key.to_string() });
+    }
+}
+
+/// Logs `change` after a collection command applied it to `key` in place,
+/// deleting the key instead if the collection is now empty.
+pub fn log_change(db: &mut Keyspace, db_instance: &DbInstance, key: &str, change: Change) {
+    let Some(entry) = db.get_mut(key) else {
+        return;
+    };
+    if entry.value.is_empty_collection() {
+        db.remove(key);
+        db_instance.log(LogRecord::Del { key: key.to_string() });
     } else {
-        "Unknown command".to_string()  // Fallback for invalid commands
+        db_instance.log_change(key, entry, change);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::TestDb;
+
+    #[test]
+    fn literal_lengths_of_valid_and_malformed_lines() {
+        assert_eq!(literal_lengths("SET(\"k\",{5})"), Some(vec![5]));
+        assert_eq!(literal_lengths("MSET({1},{2},\"v\")"), Some(vec![1, 2]));
+        assert_eq!(literal_lengths("GET(\"k\")"), Some(Vec::new()));
+        // Malformed lines that don't announce a literal are just parse errors
+        assert_eq!(literal_lengths("GET(\"k\""), Some(Vec::new()));
+        assert_eq!(literal_lengths("SET(\"k\",\"{x}\""), Some(Vec::new()));
+        // Otherwise the payload that follows can't be skipped
+        assert_eq!(literal_lengths("SET(\"k\",{5})x"), None);
+        assert_eq!(literal_lengths("SET(\"k\" {12})"), None);
+    }
+
+    #[test]
+    fn scan_cursor_round_trips() {
+        for key in ["", "0", "user:42", "héllo wörld", "\u{10FFFF}", "a\nb"] {
+            let cursor = encode_cursor(key);
+            assert!(cursor.bytes().all(|b| b.is_ascii_hexdigit()), "{}", cursor);
+            assert_eq!(decode_cursor(cursor.as_bytes()).as_deref(), Some(key));
+        }
+        assert_eq!(decode_cursor(b"6B6579"), Some("key".to_string()));
+    }
+
+    fn millis(s: &str) -> Result<u128, String> {
+        parse_duration(s).map(|d| d.as_millis())
+    }
+
+    #[test]
+    fn parses_duration_units() {
+        assert_eq!(millis("250ms"), Ok(250));
+        assert_eq!(millis("10s"), Ok(10_000));
+        assert_eq!(millis("5m"), Ok(300_000));
+        assert_eq!(millis("2h"), Ok(7_200_000));
+        assert_eq!(millis("1d"), Ok(86_400_000));
+        assert_eq!(millis("1w"), Ok(604_800_000));
+        assert_eq!(millis("1h30m15s"), Ok(5_415_000));
+        assert_eq!(millis("0s"), Ok(0));
+
+        assert_eq!(millis(""), Err("Empty TTL provided".to_string()));
+        assert_eq!(millis("10"), Err("Invalid TTL unit (use ms, s, m, h, d or w)".to_string()));
+        assert_eq!(millis("10y"), Err("Invalid TTL unit (use ms, s, m, h, d or w)".to_string()));
+        assert_eq!(millis("s"), Err("Invalid TTL number".to_string()));
+        assert_eq!(millis("-1s"), Err("Invalid TTL number".to_string()));
+        assert_eq!(millis("1.5s"), Err("Invalid TTL unit (use ms, s, m, h, d or w)".to_string()));
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        let too_large = Err("TTL is too large".to_string());
+        // The number itself, the product with its unit, and the sum of parts
+        assert_eq!(millis("99999999999999999999s"), too_large);
+        assert_eq!(millis(&format!("{}w", u64::MAX / 1000)), too_large);
+        assert_eq!(millis(&format!("{}ms{}ms", u64::MAX, 1)), too_large);
+        assert_eq!(millis(&format!("P{}DT{}H", u64::MAX / 86_400_000, u64::MAX / 3_600_000)), too_large);
+    }
+
+    #[test]
+    fn parses_iso_durations() {
+        assert_eq!(millis("PT15M"), Ok(900_000));
+        assert_eq!(millis("P1DT12H"), Ok(129_600_000));
+        assert_eq!(millis("P2W"), Ok(1_209_600_000));
+        assert_eq!(millis("PT1H2M3S"), Ok(3_723_000));
+
+        let invalid = Err("Invalid ISO-8601 duration (use e.g. PT15M or P1DT12H)".to_string());
+        assert_eq!(millis("P"), invalid);
+        assert_eq!(millis("PT"), invalid);
+        assert_eq!(millis("P1DT"), invalid);
+        assert_eq!(millis("P1H"), invalid);
+        assert_eq!(millis("PT1D"), invalid);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parses_deadlines() {
+        let in_an_hour = chrono::Utc::now() + chrono::Duration::hours(1);
+        let ttl = millis(&format!("@{}", in_an_hour.to_rfc3339())).unwrap();
+        assert!(ttl > 3_590_000 && ttl <= 3_600_000, "{}", ttl);
+
+        // A deadline in the past expires right away
+        assert_eq!(millis("@2000-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(
+            millis("@tomorrow"),
+            Err("Invalid TTL deadline (use e.g. @2026-12-31T00:00:00Z)".to_string())
+        );
+    }
+
+    #[test]
+    fn expire_accepts_seconds_and_durations() {
+        let db = TestDb::new("parser_test_expire");
+        db.run("SET(\"k\",\"v\")");
+
+        assert_eq!(db.run("EXPIRE(\"k\",\"100\")"), "OK");
+        assert_eq!(db.run("TTL(\"k\")"), "100");
+        assert_eq!(db.run("EXPIRE(\"k\",\"2h30m\")"), "OK");
+        assert_eq!(db.run("TTL(\"k\")"), "9000");
+        assert_eq!(db.run("EXPIRE(\"k\",\"PT1M\")"), "OK");
+        assert_eq!(db.run("TTL(\"k\")"), "60");
+        assert_eq!(db.run("EXPIRE(\"k\",\"1500ms\")"), "OK");
+        assert_eq!(db.run("PTTL(\"k\")").parse::<u64>().map(|ms| ms <= 1500 && ms > 1000), Ok(true));
+
+        assert_eq!(db.run("EXPIRE(\"k\",\"10x\")"), "Error: Invalid TTL unit (use ms, s, m, h, d or w)");
+        assert_eq!(db.run("EXPIRE(\"k\",\"-5\")"), "Error: Invalid TTL number");
+        assert_eq!(db.run("EXPIRE(\"k\",\"99999999999999999999\")"), "Error: TTL is too large");
+        assert!(matches!(db.reply("EXPIRE(\"missing\",\"10s\")"), Reply::Error(ErrorCode::KeyNotFound, _)));
+    }
+
+    fn is_error(reply: Reply, code: ErrorCode) -> bool {
+        matches!(reply, Reply::Error(c, _) if c == code)
+    }
+
+    #[test]
+    fn expired_keys_are_missing_for_del() {
+        let db = TestDb::new("parser_test_del_expired");
+        db.run("SET(\"a\",\"1\",\"1ms\")");
+        db.run("SET(\"b\",\"1\",\"1ms\")");
+        db.run("SET(\"c\",\"1\")");
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(is_error(db.reply("DEL(\"a\")"), ErrorCode::KeyNotFound));
+        assert!(is_error(db.reply("DEL(\"b\",VERSION,\"2\")"), ErrorCode::KeyNotFound));
+        assert_eq!(db.run("SET(\"a\",\"1\",\"1ms\")"), "OK");
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(db.run("MDEL(\"a\",\"c\",\"missing\")"), "1");
+        assert_eq!(db.run("DBSIZE()"), "0");
+    }
+
+    #[test]
+    fn exists_type_dbsize_and_flushdb() {
+        let db = TestDb::new("parser_test_key_management");
+        db.run("SET(\"s\",\"v\")");
+        db.run("RPUSH(\"l\",\"a\")");
+        db.run("HSET(\"h\",\"f\",\"v\")");
+        db.run("SADD(\"set\",\"m\")");
+        db.run("ZADD(\"z\",\"1\",\"m\")");
+        db.run("JSON.SET(\"j\",\"$\",\"{}\")");
+        db.run("SET(\"gone\",\"v\",\"1ms\")");
+        std::thread::sleep(Duration::from_millis(5));
+
+        for (key, type_name) in [("s", "string"), ("l", "list"), ("h", "hash"), ("set", "set"), ("z", "zset"), ("j", "json")] {
+            assert_eq!(db.run(&format!("TYPE(\"{}\")", key)), type_name);
+        }
+        assert_eq!(db.run("TYPE(\"gone\")"), "none");
+        assert_eq!(db.run("TYPE(\"missing\")"), "none");
+        // Keys are counted once per argument
+        assert_eq!(db.run("EXISTS(\"s\",\"s\",\"gone\",\"missing\",\"l\")"), "3");
+        assert_eq!(db.run("DBSIZE()"), "6");
+
+        assert_eq!(db.run("FLUSHDB()"), "OK");
+        assert_eq!(db.run("DBSIZE()"), "0");
+        assert_eq!(db.run("EXISTS(\"s\")"), "0");
+    }
+
+    #[test]
+    fn rename_moves_value_and_ttl() {
+        let mut db = TestDb::new("parser_test_rename");
+        db.run("SET(\"a\",\"1\",\"1h\")");
+        db.run("SET(\"b\",\"2\")");
+
+        assert_eq!(db.run("RENAME(\"a\",\"c\")"), "OK");
+        assert_eq!(db.run("GET(\"c\")"), "1");
+        assert_eq!(db.run("TTL(\"c\")"), "3600");
+        assert!(is_error(db.reply("GET(\"a\")"), ErrorCode::KeyNotFound));
+        assert!(is_error(db.reply("RENAME(\"a\",\"d\")"), ErrorCode::KeyNotFound));
+
+        // RENAME replaces the target, RENAMENX leaves it alone
+        assert_eq!(db.run("RENAMENX(\"c\",\"b\")"), "0");
+        assert_eq!(db.run("GET(\"b\")"), "2");
+        assert_eq!(db.run("RENAME(\"c\",\"b\")"), "OK");
+        assert_eq!(db.run("GET(\"b\")"), "1");
+        assert_eq!(db.run("RENAMENX(\"b\",\"e\")"), "1");
+        // Renaming a key onto itself keeps it
+        assert_eq!(db.run("RENAME(\"e\",\"e\")"), "OK");
+        assert_eq!(db.run("GET(\"e\")"), "1");
+
+        db.reload();
+        assert_eq!(db.run("GET(\"e\")"), "1");
+        assert_eq!(db.run("DBSIZE()"), "1");
+    }
+
+    #[test]
+    fn copy_within_and_across_databases() {
+        let db = TestDb::new("parser_test_copy");
+        let other = TestDb::new("parser_test_copy_target");
+        db.db_map.lock().unwrap().insert(other.name.clone(), other.instance().clone());
+        db.run("RPUSH(\"l\",\"a\",\"b\")");
+        db.run("SET(\"s\",\"v\")");
+
+        assert_eq!(db.run("COPY(\"l\",\"l2\")"), "1");
+        // The copy is independent of the original
+        db.run("RPUSH(\"l2\",\"c\")");
+        assert_eq!(db.run("LLEN(\"l\")"), "2");
+        assert_eq!(db.run("LLEN(\"l2\")"), "3");
+
+        assert_eq!(db.run("COPY(\"s\",\"l2\")"), "0");
+        assert_eq!(db.run("TYPE(\"l2\")"), "list");
+        assert_eq!(db.run("COPY(\"s\",\"l2\",REPLACE)"), "1");
+        assert_eq!(db.run("GET(\"l2\")"), "v");
+        assert!(is_error(db.reply("COPY(\"missing\",\"x\")"), ErrorCode::KeyNotFound));
+
+        assert_eq!(db.run("COPY(\"s\",\"s\",DB,\"parser_test_copy_target\")"), "1");
+        assert_eq!(other.run("GET(\"s\")"), "v");
+        assert!(is_error(db.reply("COPY(\"s\",\"s\",DB,\"parser_test_no_such_db\")"), ErrorCode::DbNotFound));
+    }
+
+    fn args(words: &[&[u8]]) -> Vec<Vec<u8>> {
+        words.iter().map(|word| word.to_vec()).collect()
+    }
+
+    #[test]
+    fn mset_is_all_or_nothing() {
+        let mut db = TestDb::new("parser_test_mset");
+        db.run("SET(\"a\",\"old\")");
+
+        // An invalid key anywhere leaves every key untouched
+        let reply = execute("MSET", args(&[b"a", b"1", b"b", b"2", b"\xff", b"3"]), &db.db, &db.db_map);
+        assert!(is_error(reply, ErrorCode::InvalidArgument));
+        assert_eq!(db.run("MGET(\"a\",\"b\")"), "old\n(nil)");
+        assert!(db.run("MSET(\"a\",\"1\",\"b\")").starts_with("Usage"));
+        assert_eq!(db.run("GET(\"a\")"), "old");
+
+        // MSETNX writes nothing if any key exists
+        assert_eq!(db.run("MSETNX(\"b\",\"2\",\"a\",\"1\")"), "0");
+        assert_eq!(db.run("EXISTS(\"b\")"), "0");
+        assert_eq!(db.run("MSETNX(\"b\",\"2\",\"c\",\"3\")"), "1");
+
+        // MSET replaces values of any type and drops their TTL
+        db.run("RPUSH(\"l\",\"x\")");
+        db.run("EXPIRE(\"a\",\"1h\")");
+        assert_eq!(db.run("MSET(\"a\",\"1\",\"l\",\"2\",\"a\",\"3\")"), "OK");
+        assert_eq!(db.run("MGET(\"a\",\"l\",\"b\",\"c\",\"missing\")"), "3\n2\n2\n3\n(nil)");
+        assert_eq!(db.run("TTL(\"a\")"), "-1");
+
+        db.reload();
+        assert_eq!(db.run("MGET(\"a\",\"l\",\"b\",\"c\")"), "3\n2\n2\n3");
+    }
+
+    #[test]
+    fn counters_add_atomically_and_keep_the_ttl() {
+        let mut db = TestDb::new("parser_test_counters");
+        assert_eq!(db.run("INCR(\"n\")"), "1");
+        assert_eq!(db.run("INCRBY(\"n\",\"41\")"), "42");
+        assert_eq!(db.run("DECR(\"n\")"), "41");
+        assert_eq!(db.run("DECRBY(\"n\",\"50\")"), "-9");
+        assert_eq!(db.run("GET(\"n\")"), "-9");
+
+        db.run("EXPIRE(\"n\",\"1h\")");
+        assert_eq!(db.run("INCR(\"n\")"), "-8");
+        assert_eq!(db.run("TTL(\"n\")"), "3600");
+        assert_eq!(db.run("INCRBYFLOAT(\"n\",\"0.5\")"), "-7.5");
+        assert_eq!(db.run("TTL(\"n\")"), "3600");
+
+        db.reload();
+        assert_eq!(db.run("GET(\"n\")"), "-7.5");
+        assert_eq!(db.run("TTL(\"n\")"), "3600");
+    }
+
+    #[test]
+    fn counters_reject_overflow_and_other_values() {
+        let db = TestDb::new("parser_test_counter_errors");
+        db.run(&format!("SET(\"max\",\"{}\")", i64::MAX));
+        db.run(&format!("SET(\"min\",\"{}\")", i64::MIN));
+        db.run("SET(\"text\",\"abc\")");
+        db.run("RPUSH(\"list\",\"1\")");
+
+        assert!(is_error(db.reply("INCR(\"max\")"), ErrorCode::InvalidArgument));
+        assert!(is_error(db.reply("DECR(\"min\")"), ErrorCode::InvalidArgument));
+        assert!(is_error(db.reply("INCRBY(\"min\",\"-1\")"), ErrorCode::InvalidArgument));
+        // Negating the decrement would overflow by itself
+        assert!(is_error(db.reply(&format!("DECRBY(\"n\",\"{}\")", i64::MIN)), ErrorCode::InvalidArgument));
+        assert_eq!(db.run("GET(\"max\")"), i64::MAX.to_string());
+        assert_eq!(db.run("GET(\"min\")"), i64::MIN.to_string());
+        assert_eq!(db.run("EXISTS(\"n\")"), "0");
+
+        assert!(is_error(db.reply("INCR(\"text\")"), ErrorCode::InvalidArgument));
+        assert!(is_error(db.reply("INCRBY(\"n\",\"1.5\")"), ErrorCode::InvalidArgument));
+        assert!(is_error(db.reply("INCRBYFLOAT(\"text\",\"1\")"), ErrorCode::InvalidArgument));
+        assert!(is_error(db.reply("INCRBYFLOAT(\"n\",\"inf\")"), ErrorCode::InvalidArgument));
+        assert!(is_error(db.reply("INCRBYFLOAT(\"n\",\"NaN\")"), ErrorCode::InvalidArgument));
+        db.run("SET(\"big\",\"1e308\")");
+        assert!(is_error(db.reply("INCRBYFLOAT(\"big\",\"1e308\")"), ErrorCode::InvalidArgument));
+        assert_eq!(db.run("GET(\"text\")"), "abc");
+        assert_eq!(db.run("GET(\"big\")"), "1e308");
+
+        assert!(is_error(db.reply("INCR(\"list\")"), ErrorCode::WrongType));
+        assert!(is_error(db.reply("INCRBYFLOAT(\"list\",\"1\")"), ErrorCode::WrongType));
+    }
+
+    #[test]
+    fn conditional_set_options() {
+        let db = TestDb::new("parser_test_set_options");
+        assert_eq!(db.run("SET(\"k\",\"1\",XX)"), "(nil)");
+        assert_eq!(db.run("EXISTS(\"k\")"), "0");
+        assert_eq!(db.run("SET(\"k\",\"1\",NX)"), "OK");
+        assert_eq!(db.run("SET(\"k\",\"2\",NX)"), "(nil)");
+        assert_eq!(db.run("SET(\"k\",\"2\",\"1h\",XX,GET)"), "1");
+        assert_eq!(db.run("SET(\"k\",\"3\",KEEPTTL)"), "OK");
+        assert_eq!(db.run("TTL(\"k\")"), "3600");
+        assert_eq!(db.run("SET(\"k\",\"4\")"), "OK");
+        assert_eq!(db.run("TTL(\"k\")"), "-1");
+        assert!(is_error(db.reply("SET(\"k\",\"5\",\"1h\",KEEPTTL)"), ErrorCode::Syntax));
+
+        assert_eq!(db.run("SETNX(\"k\",\"5\")"), "0");
+        assert_eq!(db.run("SETNX(\"n\",\"5\")"), "1");
+        assert_eq!(db.run("GETSET(\"n\",\"6\")"), "5");
+        assert_eq!(db.run("GETSET(\"m\",\"1\")"), "(nil)");
+
+        db.run("RPUSH(\"l\",\"x\")");
+        assert!(is_error(db.reply("SET(\"l\",\"v\",GET)"), ErrorCode::WrongType));
+        assert_eq!(db.run("TYPE(\"l\")"), "list");
+    }
+
+    #[test]
+    fn cas_compares_value_or_version() {
+        let mut db = TestDb::new("parser_test_cas");
+        db.run("SET(\"k\",\"a\",\"1h\")");
+
+        assert_eq!(db.run("CAS(\"k\",\"b\",\"c\")"), "0");
+        assert_eq!(db.run("CAS(\"k\",\"a\",\"c\")"), "1");
+        assert_eq!(db.run("GET(\"k\")"), "c");
+        // The TTL is kept unless a new one is given
+        assert_eq!(db.run("TTL(\"k\")"), "3600");
+        assert_eq!(db.run("CAS(\"k\",\"c\",\"d\",\"10s\")"), "1");
+        assert_eq!(db.run("TTL(\"k\")"), "10");
+        assert!(is_error(db.reply("CAS(\"missing\",\"a\",\"b\")"), ErrorCode::KeyNotFound));
+        assert!(is_error(db.reply("CAS(\"k\",\"d\",\"e\",\"soon\")"), ErrorCode::InvalidArgument));
+
+        let version = db.version("k").unwrap();
+        assert_eq!(db.run(&format!("CAS(\"k\",VERSION,\"{}\",\"e\")", version + 1)), "0");
+        assert_eq!(db.run(&format!("CAS(\"k\",VERSION,\"{}\",\"e\")", version)), "1");
+        assert_eq!(db.run(&format!("CAS(\"k\",VERSION,\"{}\",\"f\")", version)), "0");
+        assert_eq!(db.run("GET(\"k\")"), "e");
+        assert!(db.run("CAS(\"k\",VERSION,\"x\",\"f\")").starts_with("Usage"));
+
+        // By version any type can be replaced, by value only strings
+        db.run("RPUSH(\"l\",\"x\")");
+        assert!(is_error(db.reply("CAS(\"l\",\"x\",\"s\")"), ErrorCode::WrongType));
+        let version = db.version("l").unwrap();
+        assert_eq!(db.run(&format!("CAS(\"l\",VERSION,\"{}\",\"s\")", version)), "1");
+        assert_eq!(db.run("GET(\"l\")"), "s");
+
+        db.reload();
+        assert_eq!(db.run("GET(\"k\")"), "e");
+        assert_eq!(db.run("GET(\"l\")"), "s");
+    }
+
+    #[test]
+    fn set_and_del_check_the_version() {
+        let db = TestDb::new("parser_test_versions");
+        db.run("SET(\"k\",\"a\")");
+        let version = db.version("k").unwrap();
+
+        assert!(is_error(
+            db.reply(&format!("SET(\"k\",\"b\",VERSION,\"{}\")", version + 1)),
+            ErrorCode::VersionMismatch
+        ));
+        assert_eq!(db.run(&format!("SET(\"k\",\"b\",VERSION,\"{}\")", version)), "OK");
+        let new_version = db.version("k").unwrap();
+        assert!(new_version > version);
+
+        assert!(is_error(db.reply(&format!("DEL(\"k\",VERSION,\"{}\")", version)), ErrorCode::VersionMismatch));
+        assert_eq!(db.run("GET(\"k\")"), "b");
+        assert_eq!(db.run(&format!("DEL(\"k\",VERSION,\"{}\")", new_version)), "OK");
+        assert!(is_error(db.reply(&format!("SET(\"k\",\"c\",VERSION,\"{}\")", new_version)), ErrorCode::KeyNotFound));
+        assert!(is_error(db.reply(&format!("DEL(\"k\",VERSION,\"{}\")", new_version)), ErrorCode::KeyNotFound));
+    }
+
+    #[test]
+    fn prefix_scans_in_key_order() {
+        let db = TestDb::new("parser_test_prefix");
+        db.run("MSET(\"user:2\",\"b\",\"user:10\",\"c\",\"user:1\",\"a\",\"users\",\"x\",\"user;\",\"y\")");
+        db.run("SET(\"user:3\",\"gone\",\"1ms\")");
+        std::thread::sleep(Duration::from_millis(5));
+
+        // Lexicographic, not numeric, order; expired keys are skipped
+        assert_eq!(db.run("PREFIX(\"user:\")"), "user:1\nuser:10\nuser:2");
+        assert_eq!(db.run("PREFIX(\"user:\",\"2\")"), "user:1\nuser:10");
+        assert_eq!(db.run("PREFIX(\"user:\",\"2\",REV)"), "user:2\nuser:10");
+        assert_eq!(db.run("PREFIX(\"user:1\",WITHVALUES)"), "user:1\na\nuser:10\nc");
+        assert_eq!(db.run("PREFIX(\"nobody:\")"), "");
+        assert_eq!(db.run("PREFIX(\"\")").lines().count(), 5);
+        assert!(db.run("PREFIX(\"user:\",\"x\")").starts_with("Usage"));
+    }
+
+    #[test]
+    fn range_scans_between_bounds() {
+        let db = TestDb::new("parser_test_range");
+        db.run("MSET(\"a\",\"1\",\"b\",\"2\",\"c\",\"3\",\"d\",\"4\")");
+
+        // The start is inclusive, the end exclusive, and empty bounds are open
+        assert_eq!(db.run("RANGE(\"b\",\"d\")"), "b\nc");
+        assert_eq!(db.run("RANGE(\"\",\"c\")"), "a\nb");
+        assert_eq!(db.run("RANGE(\"bb\",\"\")"), "c\nd");
+        assert_eq!(db.run("RANGE(\"\",\"\",\"3\")"), "a\nb\nc");
+        assert_eq!(db.run("RANGE(\"\",\"\",\"3\",REV)"), "d\nc\nb");
+        assert_eq!(db.run("RANGE(\"b\",\"d\",REV,WITHVALUES)"), "c\n3\nb\n2");
+        assert_eq!(db.run("RANGE(\"c\",\"c\")"), "");
+        assert!(is_error(db.reply("RANGE(\"d\",\"a\")"), ErrorCode::InvalidArgument));
+    }
+
+    #[test]
+    fn rejects_invalid_scan_cursors() {
+        assert_eq!(decode_cursor(b"abc"), None);
+        assert_eq!(decode_cursor(b"zz"), None);
+        assert_eq!(decode_cursor(b"+f"), None);
+        assert_eq!(decode_cursor(b"-1"), None);
+        // Valid hex, but not UTF-8
+        assert_eq!(decode_cursor(b"ff"), None);
+    }
+}
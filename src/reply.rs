@@ -0,0 +1,200 @@
+// =======================================================
+// 🧠 INFO: Command Replies
+// =======================================================
+// Commands return a `Reply`; each protocol front end decides how to encode it.
+use crate::db::json_bytes;
+use serde_json::json;
+
+/// Classifies an error so protocol front ends can map it to their own
+/// conventions (e.g. RESP error prefixes or nil replies).
+///
+/// The names returned by `as_str` are part of the JSON response mode and
+/// must stay stable: clients match on them instead of on the message text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // NO_DATABASE: the command needs a selected database.
+    NoDatabase,
+    // KEY_NOT_FOUND: the key does not exist (or has just expired).
+    KeyNotFound,
+    // SYNTAX_ERROR: the command was malformed or had the wrong number of arguments.
+    Syntax,
+    // UNKNOWN_COMMAND: the command name is not recognised.
+    UnknownCommand,
+    // WRONG_TYPE: the key holds a value of another type (e.g. a list
+    // where a string was expected).
+    WrongType,
+    // VERSION_MISMATCH: a write conditional on a key's version found a
+    // different version.
+    VersionMismatch,
+    // INVALID_ARGUMENT: an argument was well-formed but its value was rejected
+    // (e.g. a bad TTL).
+    InvalidArgument,
+    // AUTH_REQUIRED: the database requires credentials that were not supplied.
+    AuthRequired,
+    // AUTH_FAILED: the supplied credentials were wrong.
+    AuthFailed,
+    // TOO_MANY_AUTH_ATTEMPTS: authentication failed too often; the operation
+    // was aborted or the connection is being closed.
+    TooManyAuthAttempts,
+    // UNSUPPORTED_PROTOCOL: the requested protocol version is not supported.
+    UnsupportedProtocol,
+    // DB_NOT_FOUND: the named database does not exist.
+    DbNotFound,
+    // DB_EXISTS: a database with that name already exists.
+    DbExists,
+    // INVALID_DB_NAME: the name can't be used for a database.
+    InvalidDbName,
+    // DB_ALREADY_SELECTED: the connection already has a database selected.
+    DbAlreadySelected,
+    // DB_IN_USE: the database is selected by this connection.
+    DbInUse,
+    // INTERNAL_ERROR: the server failed (e.g. an I/O error).
+    Internal,
+}
+
+impl ErrorCode {
+    /// Stable name of the code as sent to JSON clients.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NoDatabase => "NO_DATABASE",
+            Self::KeyNotFound => "KEY_NOT_FOUND",
+            Self::Syntax => "SYNTAX_ERROR",
+            Self::UnknownCommand => "UNKNOWN_COMMAND",
+            Self::WrongType => "WRONG_TYPE",
+            Self::VersionMismatch => "VERSION_MISMATCH",
+            Self::InvalidArgument => "INVALID_ARGUMENT",
+            Self::AuthRequired => "AUTH_REQUIRED",
+            Self::AuthFailed => "AUTH_FAILED",
+            Self::TooManyAuthAttempts => "TOO_MANY_AUTH_ATTEMPTS",
+            Self::UnsupportedProtocol => "UNSUPPORTED_PROTOCOL",
+            Self::DbNotFound => "DB_NOT_FOUND",
+            Self::DbExists => "DB_EXISTS",
+            Self::InvalidDbName => "INVALID_DB_NAME",
+            Self::DbAlreadySelected => "DB_ALREADY_SELECTED",
+            Self::DbInUse => "DB_IN_USE",
+            Self::Internal => "INTERNAL_ERROR",
+        }
+    }
+}
+
+/// Result of executing a command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    // Command succeeded without a value.
+    Ok,
+    // Command succeeded; the message describes what happened.
+    Status(String),
+    // The server is waiting for the client to answer a question
+    // (e.g. a username during authentication).
+    Prompt(String),
+    // A stored value (arbitrary bytes).
+    Value(Vec<u8>),
+    // No value, e.g. a missing key inside a multi-key result.
+    Nil,
+    // An integer result.
+    Int(i64),
+    // Several results.
+    Array(Vec<Reply>),
+    // Key/value pairs (a map in RESP3, a flat array elsewhere).
+    Map(Vec<(Reply, Reply)>),
+    // The command failed; the message is shown to text protocol clients verbatim.
+    Error(ErrorCode, String),
+}
+
+impl Reply {
+    /// Builds an error reply.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::Error(code, message.into())
+    }
+
+    /// Encodes the reply for the line-based text protocol (without the
+    /// trailing line break).
+    pub fn to_text(&self) -> Vec<u8> {
+        match self {
+            Self::Ok => b"OK".to_vec(),
+            Self::Status(message) | Self::Prompt(message) => message.clone().into_bytes(),
+            Self::Value(value) => format_value(value),
+            Self::Nil => b"(nil)".to_vec(),
+            Self::Int(n) => n.to_string().into_bytes(),
+            Self::Array(items) => items
+                .iter()
+                .map(Self::to_text)
+                .collect::<Vec<_>>()
+                .join(&b'\n'),
+            Self::Map(pairs) => pairs
+                .iter()
+                .flat_map(|(key, value)| [key.to_text(), value.to_text()])
+                .collect::<Vec<_>>()
+                .join(&b'\n'),
+            Self::Error(_, message) => message.clone().into_bytes(),
+        }
+    }
+
+    /// Encodes the reply as a single-line JSON object for the JSON response
+    /// mode (without the trailing line break):
+    ///
+    /// - `{"status":"ok"}`, optionally with a `"value"` or `"message"`
+    /// - `{"status":"error","code":"KEY_NOT_FOUND","message":"..."}`
+    /// - `{"status":"prompt","message":"Username:"}`
+    ///
+    /// Values are JSON strings when they are UTF-8, otherwise `{"base64":"..."}`.
+    /// Maps become objects that keep the order of their pairs.
+    pub fn to_json(&self) -> Vec<u8> {
+        let object = match self {
+            Self::Ok => json!({ "status": "ok" }),
+            Self::Status(message) => json!({ "status": "ok", "message": message }),
+            Self::Prompt(message) => json!({ "status": "prompt", "message": message }),
+            Self::Error(code, message) => json!({
+                "status": "error",
+                "code": code.as_str(),
+                "message": message,
+            }),
+            other => json!({ "status": "ok", "value": other.to_json_value() }),
+        };
+        object.to_string().into_bytes()
+    }
+
+    /// JSON form of a reply nested inside a value.
+    fn to_json_value(&self) -> serde_json::Value {
+        match self {
+            Self::Ok => json!("OK"),
+            Self::Status(message) | Self::Prompt(message) => json!(message),
+            Self::Value(value) => json_bytes::serialize(value, serde_json::value::Serializer)
+                .unwrap_or(serde_json::Value::Null),
+            Self::Nil => serde_json::Value::Null,
+            Self::Int(n) => json!(n),
+            Self::Array(items) => items.iter().map(Self::to_json_value).collect(),
+            Self::Map(pairs) => serde_json::Value::Object(
+                pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        (String::from_utf8_lossy(&key.to_text()).into_owned(), value.to_json_value())
+                    })
+                    .collect(),
+            ),
+            Self::Error(code, message) => json!({ "code": code.as_str(), "message": message }),
+        }
+    }
+}
+
+/// Formats a value for the text protocol: as-is when it is single-line UTF-8,
+/// otherwise as a binary literal ({N}, a line break, then N raw bytes) so
+/// clients can frame it by length. Values that read like a literal header or
+/// like `(nil)` are framed too, so they can't be mistaken for one.
+fn format_value(value: &[u8]) -> Vec<u8> {
+    let looks_like_literal = value.len() > 2
+        && value.starts_with(b"{")
+        && value.ends_with(b"}")
+        && value[1..value.len() - 1].iter().all(u8::is_ascii_digit);
+    let looks_like_nil = value == b"(nil)";
+    let is_plain = std::str::from_utf8(value)
+        .map(|text| !text.contains(['\r', '\n']) && !looks_like_literal && !looks_like_nil)
+        .unwrap_or(false);
+
+    if is_plain {
+        return value.to_vec();
+    }
+    let mut out = format!("{{{}}}\n", value.len()).into_bytes();
+    out.extend_from_slice(value);
+    out
+}
@@ -0,0 +1,486 @@
+// =======================================================
+// 🧠 INFO: Redis RESP2/RESP3 Protocol Handler
+// =======================================================
+// Lets standard Redis clients (redis-cli, redis-benchmark, client libraries)
+// talk to db-server. Connections are detected by their first byte ('*') and
+// served here instead of by the line protocol in main.rs.
+//
+// Mapping onto db-server concepts:
+// - SELECT <name> selects the database called <name> (loading it if needed)
+// - AUTH [username] <password> stores credentials checked by SELECT (or checks
+//   them against the selected database); a lone password uses username "default"
+// - HELLO [2|3] [AUTH username password] switches between RESP2 and RESP3
+// - SET supports EX/PX/NX/XX/GET/KEEPTTL; DEL accepts several keys; EXPIRE replies 1/0
+// - GET of a missing key replies nil; other commands on one reply "-ERR no such key"
+// - Every other command is executed by the shared command parser
+
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::db::{self, DbInstance, DbMap};
+use crate::logger::log_info;
+use crate::parser;
+use crate::reply::{ErrorCode, Reply};
+
+/// Most arguments a single command may carry.
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// Most arguments reserved before they arrive; larger commands grow as read.
+const PREALLOCATED_ARGS: usize = 64;
+
+/// Most bytes the bulk strings of a single command may total, the same budget
+/// as the binary literals of a line protocol command.
+const MAX_COMMAND_SIZE: usize = parser::MAX_LITERAL_SIZE;
+
+/// Longest line (inline command or array/bulk header) read before the
+/// connection is dropped, as with Redis's inline limit.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Per-connection protocol state.
+struct Session {
+    // Negotiated protocol version (2 or 3).
+    protocol: u8,
+    // Credentials given with AUTH or HELLO.
+    credentials: Option<(String, String)>,
+    // Currently selected database.
+    db: Option<Arc<DbInstance>>,
+}
+
+/// Serves a RESP connection until the client disconnects or sends QUIT.
+pub async fn handle_connection(mut socket: TcpStream, db_map: DbMap) {
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+    let mut session = Session { protocol: 2, credentials: None, db: None };
+
+    loop {
+        let args = match read_command(&mut reader).await {
+            Ok(Some(args)) if args.is_empty() => continue,
+            Ok(Some(args)) => args,
+            Ok(None) => break, // Connection closed by client
+            Err(e) => {
+                // The stream can't be re-synchronised after a framing error
+                let _ = writer
+                    .write_all(format!("-ERR Protocol error: {}\r\n", e).as_bytes())
+                    .await;
+                break;
+            }
+        };
+
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let quit = name == "QUIT";
+        let reply = run_command(&name, args[1..].to_vec(), &mut session, &db_map).await;
+
+        let mut out = Vec::new();
+        encode(&reply, session.protocol, &mut out);
+        if let Err(e) = writer.write_all(&out).await {
+            eprintln!("Error writing to socket: {}", e);
+            break;
+        }
+        if quit {
+            break;
+        }
+    }
+}
+
+/// Reads one command: a RESP array of bulk strings, or an inline command
+/// (space-separated words on one line). Returns `None` at end of stream.
+async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let line = match read_line(reader).await? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    if !line.starts_with(b"*") {
+        let args = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        return Ok(Some(args));
+    }
+
+    let count = parse_length(&line[1..], MAX_ARGS)?;
+    // Buffers only grow as data arrives, so announced sizes can't exhaust memory
+    let mut args = Vec::with_capacity(count.min(PREALLOCATED_ARGS));
+    let mut budget = MAX_COMMAND_SIZE;
+    for _ in 0..count {
+        let header = read_line(reader).await?.ok_or_else(|| protocol_error("unexpected end of stream"))?;
+        if !header.starts_with(b"$") {
+            return Err(protocol_error("expected '$'"));
+        }
+        let len = parse_length(&header[1..], budget)?;
+        budget -= len;
+
+        let mut arg = Vec::new();
+        (&mut *reader).take(len as u64 + 2).read_to_end(&mut arg).await?;
+        if arg.len() < len + 2 {
+            return Err(protocol_error("unexpected end of stream"));
+        }
+        if !arg.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not terminated by CRLF"));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Reads a line without its CRLF terminator. Returns `None` at end of stream.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    if (&mut *reader).take(MAX_LINE as u64).read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.len() == MAX_LINE && !line.ends_with(b"\n") {
+        return Err(protocol_error("line too long"));
+    }
+    while line.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_length(digits: &[u8], max: usize) -> io::Result<usize> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&len| len <= max)
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn wrong_args(name: &str) -> Reply {
+    Reply::error(
+        ErrorCode::Syntax,
+        format!("wrong number of arguments for '{}' command", name.to_lowercase()),
+    )
+}
+
+/// Executes one command against the session.
+async fn run_command(name: &str, args: Vec<Vec<u8>>, session: &mut Session, db_map: &DbMap) -> Reply {
+    match name {
+        "PING" => match args.len() {
+            0 => Reply::Value(b"PONG".to_vec()),
+            1 => Reply::Value(args[0].clone()),
+            _ => wrong_args(name),
+        },
+        "ECHO" if args.len() == 1 => Reply::Value(args[0].clone()),
+        "QUIT" => Reply::Ok,
+        // Client bookkeeping sent by most libraries on connect
+        "CLIENT" => Reply::Ok,
+        "COMMAND" => Reply::Array(Vec::new()),
+        "HELLO" => hello(args, session),
+        "AUTH" => auth(args, session).await,
+        "SELECT" if args.len() == 1 => select(&String::from_utf8_lossy(&args[0]), session, db_map).await,
+        "SET" => set(args, session, db_map),
+        // DEL key [key ...] replies with the number of keys removed
+        "DEL" if !args.is_empty() => parser::execute("MDEL", args, &session.db, db_map),
+        // EXPIRE key seconds replies 1 if the TTL was set, 0 if the key doesn't exist
+        "EXPIRE" => match parser::execute("EXPIRE", args, &session.db, db_map) {
+            Reply::Ok => Reply::Int(1),
+            Reply::Error(ErrorCode::KeyNotFound, _) => Reply::Int(0),
+            other => other,
+        },
+        // EXPIREAT key unix-seconds, likewise
+        "EXPIREAT" => match parser::execute("EXPIREAT", args, &session.db, db_map) {
+            Reply::Ok => Reply::Int(1),
+            Reply::Error(ErrorCode::KeyNotFound, _) => Reply::Int(0),
+            other => other,
+        },
+        // TTL/PTTL reply -2 for missing keys
+        "TTL" | "PTTL" => match parser::execute(name, args, &session.db, db_map) {
+            Reply::Error(ErrorCode::KeyNotFound, _) => Reply::Int(-2),
+            other => other,
+        },
+        // PERSIST replies 0 for missing keys
+        "PERSIST" => match parser::execute("PERSIST", args, &session.db, db_map) {
+            Reply::Error(ErrorCode::KeyNotFound, _) => Reply::Int(0),
+            other => other,
+        },
+        // TYPE replies with a simple string
+        "TYPE" => match parser::execute("TYPE", args, &session.db, db_map) {
+            Reply::Value(name) => Reply::Status(String::from_utf8_lossy(&name).into_owned()),
+            other => other,
+        },
+        "ECHO" | "SELECT" | "DEL" => wrong_args(name),
+        _ => match parser::execute(name, args, &session.db, db_map) {
+            // Reading a missing key replies nil in Redis; anything else fails
+            Reply::Error(ErrorCode::KeyNotFound, _) if matches!(name, "GET" | "GETMETA") => Reply::Nil,
+            Reply::Error(ErrorCode::KeyNotFound, _) => Reply::error(ErrorCode::KeyNotFound, "no such key"),
+            other => other,
+        },
+    }
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]
+fn hello(args: Vec<Vec<u8>>, session: &mut Session) -> Reply {
+    let mut args = args.into_iter().map(|a| String::from_utf8_lossy(&a).into_owned());
+
+    if let Some(version) = args.next() {
+        match version.as_str() {
+            "2" => session.protocol = 2,
+            "3" => session.protocol = 3,
+            _ => return Reply::error(ErrorCode::UnsupportedProtocol, "unsupported protocol version"),
+        }
+    }
+
+    while let Some(option) = args.next() {
+        match option.to_ascii_uppercase().as_str() {
+            "AUTH" => match (args.next(), args.next()) {
+                (Some(username), Some(password)) => {
+                    session.credentials = Some((username, password));
+                }
+                _ => return Reply::error(ErrorCode::Syntax, "Syntax error in HELLO option 'AUTH'"),
+            },
+            "SETNAME" => {
+                args.next();
+            }
+            other => {
+                return Reply::error(ErrorCode::Syntax, format!("Syntax error in HELLO option '{}'", other));
+            }
+        }
+    }
+
+    let properties = [
+        ("server", Reply::Value(b"db-server".to_vec())),
+        ("version", Reply::Value(env!("CARGO_PKG_VERSION").as_bytes().to_vec())),
+        ("proto", Reply::Int(session.protocol as i64)),
+        ("mode", Reply::Value(b"standalone".to_vec())),
+        ("role", Reply::Value(b"master".to_vec())),
+    ];
+    Reply::Map(
+        properties
+            .into_iter()
+            .map(|(key, value)| (Reply::Value(key.as_bytes().to_vec()), value))
+            .collect(),
+    )
+}
+
+/// AUTH [username] password
+async fn auth(args: Vec<Vec<u8>>, session: &mut Session) -> Reply {
+    let mut args: Vec<String> = args.into_iter().map(|a| String::from_utf8_lossy(&a).into_owned()).collect();
+    let (username, password) = match args.len() {
+        1 => ("default".to_string(), args.remove(0)),
+        2 => {
+            let password = args.remove(1);
+            (args.remove(0), password)
+        }
+        _ => return wrong_args("AUTH"),
+    };
+
+    // With a database selected the credentials are checked right away
+    if let Some(db) = &session.db
+        && db.require_auth
+        && !matches!(db.check_credentials(&username, &password).await, Ok(true))
+    {
+        return wrong_password();
+    }
+
+    session.credentials = Some((username, password));
+    Reply::Ok
+}
+
+/// SELECT name
+async fn select(name: &str, session: &mut Session, db_map: &DbMap) -> Reply {
+    let db_instance = match db::get_or_load(db_map, name) {
+        Some(db_instance) => db_instance,
+        None => return Reply::error(ErrorCode::DbNotFound, format!("Database '{}' not found", name)),
+    };
+
+    if db_instance.require_auth {
+        let Some((username, password)) = &session.credentials else {
+            return Reply::error(ErrorCode::AuthRequired, "Authentication required.");
+        };
+        match db_instance.check_credentials(username, password).await {
+            Ok(true) => {}
+            Ok(false) => return wrong_password(),
+            Err(e) => {
+                log_info(&format!("⚠️ Error verifying password for '{}': {}", name, e));
+                return Reply::error(ErrorCode::Internal, "Authentication error.");
+            }
+        }
+    }
+
+    session.db = Some(Arc::new(db_instance));
+    Reply::Ok
+}
+
+fn wrong_password() -> Reply {
+    Reply::error(
+        ErrorCode::AuthFailed,
+        "invalid username-password pair or user is disabled.",
+    )
+}
+
+/// SET key value [EX seconds | PX milliseconds] [NX | XX] [GET] [KEEPTTL] [VERSION n]
+fn set(args: Vec<Vec<u8>>, session: &Session, db_map: &DbMap) -> Reply {
+    if args.len() < 2 {
+        return wrong_args("SET");
+    }
+    let mut args = args.into_iter();
+    let key = args.next().unwrap();
+    let value = args.next().unwrap();
+
+    let mut set_args = vec![key, value];
+    while let Some(option) = args.next() {
+        let option = option.to_ascii_uppercase();
+        match option.as_slice() {
+            b"EX" | b"PX" => {
+                let amount = args.next().map(|a| String::from_utf8_lossy(&a).into_owned());
+                let amount = amount.filter(|amount| !amount.is_empty() && amount.bytes().all(|b| b.is_ascii_digit()));
+                let Some(amount) = amount else {
+                    return Reply::error(ErrorCode::InvalidArgument, "invalid expire time in 'set' command");
+                };
+                let unit = if option == b"EX" { "s" } else { "ms" };
+                set_args.push(format!("{}{}", amount, unit).into_bytes());
+            }
+            // Conditions are handled by the shared parser
+            b"NX" | b"XX" | b"GET" | b"KEEPTTL" => set_args.push(option),
+            b"VERSION" => match args.next() {
+                Some(version) => set_args.extend([option, version]),
+                None => return Reply::error(ErrorCode::Syntax, "syntax error"),
+            },
+            _ => return Reply::error(ErrorCode::Syntax, "syntax error"),
+        }
+    }
+
+    parser::execute("SET", set_args, &session.db, db_map)
+}
+
+/// Encodes a reply in RESP2 or RESP3.
+fn encode(reply: &Reply, protocol: u8, out: &mut Vec<u8>) {
+    match reply {
+        Reply::Ok => out.extend_from_slice(b"+OK\r\n"),
+        Reply::Status(message) | Reply::Prompt(message) => {
+            out.extend_from_slice(format!("+{}\r\n", message.replace(['\r', '\n'], " ")).as_bytes());
+        }
+        Reply::Value(value) => {
+            out.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+            out.extend_from_slice(value);
+            out.extend_from_slice(b"\r\n");
+        }
+        Reply::Int(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+        Reply::Nil => match protocol {
+            3 => out.extend_from_slice(b"_\r\n"),
+            _ => out.extend_from_slice(b"$-1\r\n"),
+        },
+        Reply::Array(items) => {
+            out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+            for item in items {
+                encode(item, protocol, out);
+            }
+        }
+        Reply::Map(pairs) => {
+            match protocol {
+                3 => out.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes()),
+                _ => out.extend_from_slice(format!("*{}\r\n", pairs.len() * 2).as_bytes()),
+            }
+            for (key, value) in pairs {
+                encode(key, protocol, out);
+                encode(value, protocol, out);
+            }
+        }
+        Reply::Error(code, message) => {
+            let prefix = match code {
+                ErrorCode::AuthRequired => "NOAUTH",
+                ErrorCode::AuthFailed => "WRONGPASS",
+                ErrorCode::UnsupportedProtocol => "NOPROTO",
+                ErrorCode::WrongType => "WRONGTYPE",
+                _ => "ERR",
+            };
+            let message = message.strip_prefix("Error: ").unwrap_or(message);
+            // Messages may not contain line breaks in RESP
+            let message = message.replace(['\r', '\n'], " ");
+            out.extend_from_slice(format!("-{} {}\r\n", prefix, message).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut input: &[u8]) -> io::Result<Option<Vec<Vec<u8>>>> {
+        read_command(&mut input).await
+    }
+
+    #[tokio::test]
+    async fn reads_arrays_and_inline_commands() {
+        let args = read(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\na\r\nb\r\n").await.unwrap();
+        assert_eq!(args, Some(vec![b"SET".to_vec(), b"k".to_vec(), b"a\r\nb".to_vec()]));
+
+        let args = read(b"GET  key\r\n").await.unwrap();
+        assert_eq!(args, Some(vec![b"GET".to_vec(), b"key".to_vec()]));
+
+        assert_eq!(read(b"").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_frames() {
+        assert!(read(b"*1\r\n+OK\r\n").await.is_err());
+        assert!(read(b"*1\r\n$3\r\nabcd\r\n").await.is_err());
+        assert!(read(b"*x\r\n").await.is_err());
+        assert!(read(b"*1\r\n$-1\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn announced_sizes_are_not_allocated_up_front() {
+        // Both fail on the missing data instead of reserving what was announced
+        let error = read(b"*1048576\r\n$1\r\na\r\n").await.unwrap_err();
+        assert_eq!(error.to_string(), "unexpected end of stream");
+        let header = format!("*1\r\n${}\r\nabc", MAX_COMMAND_SIZE);
+        let error = read(header.as_bytes()).await.unwrap_err();
+        assert_eq!(error.to_string(), "unexpected end of stream");
+    }
+
+    #[tokio::test]
+    async fn enforces_limits() {
+        let too_many = format!("*{}\r\n", MAX_ARGS + 1);
+        assert!(read(too_many.as_bytes()).await.is_err());
+
+        let too_long = format!("*1\r\n${}\r\n", MAX_COMMAND_SIZE + 1);
+        assert_eq!(read(too_long.as_bytes()).await.unwrap_err().to_string(), "invalid length");
+
+        let long_line = vec![b'a'; MAX_LINE + 10];
+        assert_eq!(read(&long_line).await.unwrap_err().to_string(), "line too long");
+        let long_header = [b"*1\r\n$".as_slice(), &vec![b'1'; MAX_LINE]].concat();
+        assert_eq!(read(&long_header).await.unwrap_err().to_string(), "line too long");
+
+        // A line just under the limit still reads
+        let mut inline = vec![b'a'; MAX_LINE - 2];
+        inline.extend_from_slice(b"\r\n");
+        assert_eq!(read(&inline).await.unwrap().unwrap()[0].len(), MAX_LINE - 2);
+    }
+
+    fn encoded(reply: &Reply, protocol: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(reply, protocol, &mut out);
+        out
+    }
+
+    #[test]
+    fn encodes_replies() {
+        assert_eq!(encoded(&Reply::Nil, 2), b"$-1\r\n");
+        assert_eq!(encoded(&Reply::Nil, 3), b"_\r\n");
+        assert_eq!(encoded(&Reply::Value(b"a\r\nb".to_vec()), 2), b"$4\r\na\r\nb\r\n");
+        assert_eq!(encoded(&Reply::Status("line\nbreak".to_string()), 2), b"+line break\r\n");
+        let map = Reply::Map(vec![(Reply::Value(b"f".to_vec()), Reply::Int(1))]);
+        assert_eq!(encoded(&map, 2), b"*2\r\n$1\r\nf\r\n:1\r\n");
+        assert_eq!(encoded(&map, 3), b"%1\r\n$1\r\nf\r\n:1\r\n");
+    }
+
+    #[test]
+    fn encodes_errors_with_redis_prefixes() {
+        let missing = Reply::error(ErrorCode::KeyNotFound, "no such key");
+        assert_eq!(encoded(&missing, 2), b"-ERR no such key\r\n");
+        assert_eq!(encoded(&parser::wrong_type(), 3)[..11], *b"-WRONGTYPE ");
+        let error = Reply::error(ErrorCode::InvalidArgument, "Error: bad\nvalue");
+        assert_eq!(encoded(&error, 2), b"-ERR bad value\r\n");
+    }
+
+}
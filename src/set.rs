@@ -0,0 +1,311 @@
+// =======================================================
+// 🧠 INFO: Set Commands
+// =======================================================
+// Sets are stored as `Value::Set`: distinct byte strings, replied in sorted
+// order. Like lists they are never left empty. SADD and SREM log only the
+// members they added or removed, while the *STORE commands log the whole
+// set they store. SUNION, SINTER and SDIFF treat missing keys as empty sets.
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::db::{DbInstance, JsonBytes, Keyspace, Value, ValueWithExpiry};
+use crate::parser::{key_arg, log_change, no_database, purge_if_expired, usage, wrong_type};
+use crate::reply::Reply;
+use crate::wal::{Change, LogRecord};
+
+type Set = BTreeSet<Vec<u8>>;
+
+/// How SUNION, SINTER and SDIFF combine the sets of their keys.
+#[derive(Clone, Copy)]
+pub enum SetOp {
+    Union,
+    Intersection,
+    // Members of the first set that are in none of the others.
+    Difference,
+}
+
+impl SetOp {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Union => "SUNION",
+            Self::Intersection => "SINTER",
+            Self::Difference => "SDIFF",
+        }
+    }
+}
+
+/// SADD("key","member",...) - creates the set if needed and replies with the
+/// number of members that were added; the TTL of an existing set is kept
+pub fn add(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 2 {
+        return usage("SADD(\"key\",\"member\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let entry = db
+                .entry(key.clone())
+                .or_insert_with(|| ValueWithExpiry::new(Value::Set(BTreeSet::new()), None));
+            let Value::Set(members) = &mut entry.value else {
+                return wrong_type();
+            };
+            let added: Vec<_> = args.filter(|member| members.insert(member.clone())).map(JsonBytes).collect();
+            let count = added.len();
+            // A new set always gains a member, so it is never left empty
+            if count > 0 {
+                db_instance.log_change(&key, entry, Change::SetAdd { members: added });
+            }
+            Reply::Int(count as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// SREM("key","member",...) - replies with the number of members removed,
+/// deleting the key if none are left
+pub fn remove(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 2 {
+        return usage("SREM(\"key\",\"member\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let members = match set_mut(&mut db, db_instance, &key) {
+                Ok(Some(members)) => members,
+                Ok(None) => return Reply::Int(0),
+                Err(e) => return e,
+            };
+
+            let removed: Vec<_> = args.filter(|member| members.remove(member)).map(JsonBytes).collect();
+            let count = removed.len();
+            if count > 0 {
+                log_change(&mut db, db_instance, &key, Change::SetRem { members: removed });
+            }
+            Reply::Int(count as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// SISMEMBER("key","member") - replies 1 if the member is in the set, 0 otherwise
+pub fn is_member(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("SISMEMBER(\"key\",\"member\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let member = args.next().unwrap();
+
+    with_set(&key, current_db_instance, |members| {
+        Reply::Int(members.is_some_and(|members| members.contains(&member)) as i64)
+    })
+}
+
+/// SMEMBERS("key") - replies with every member (empty if the key doesn't exist)
+pub fn members(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("SMEMBERS(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    with_set(&key, current_db_instance, |members| {
+        Reply::Array(members.into_iter().flatten().cloned().map(Reply::Value).collect())
+    })
+}
+
+/// SCARD("key") - replies with the number of members (0 if the key doesn't exist)
+pub fn card(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 1 {
+        return usage("SCARD(\"key\")");
+    }
+    let key = match key_arg(args.into_iter().next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    with_set(&key, current_db_instance, |members| Reply::Int(members.map_or(0, |members| members.len() as i64)))
+}
+
+/// SUNION/SINTER/SDIFF("key",...) - replies with the members of the combined sets
+pub fn combine(args: Vec<Vec<u8>>, op: SetOp, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.is_empty() {
+        return usage(&format!("{}(\"key\",...)", op.name()));
+    }
+    let keys = match args.into_iter().map(key_arg).collect::<Result<Vec<_>, _>>() {
+        Ok(keys) => keys,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match combine_sets(&mut db, db_instance, &keys, op) {
+                Ok(result) => Reply::Array(result.into_iter().map(Reply::Value).collect()),
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+/// SUNIONSTORE/SINTERSTORE/SDIFFSTORE("destination","key",...) - stores the
+/// combined sets at `destination`, replacing any value and TTL it had, and
+/// replies with the number of members. An empty result deletes `destination`
+pub fn combine_store(args: Vec<Vec<u8>>, op: SetOp, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 2 {
+        return usage(&format!("{}STORE(\"destination\",\"key\",...)", op.name()));
+    }
+    let mut keys = match args.into_iter().map(key_arg).collect::<Result<Vec<_>, _>>() {
+        Ok(keys) => keys,
+        Err(e) => return e,
+    };
+    let destination = keys.remove(0);
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let result = match combine_sets(&mut db, db_instance, &keys, op) {
+                Ok(result) => result,
+                Err(e) => return e,
+            };
+
+            let len = result.len();
+            if result.is_empty() {
+                if db.remove(&destination).is_some() {
+                    db_instance.log(LogRecord::Del { key: destination });
+                }
+            } else {
+                let mut entry = ValueWithExpiry::new(Value::Set(result), None);
+                db_instance.log_set(&destination, &mut entry);
+                db.insert(destination, entry);
+            }
+            Reply::Int(len as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// Combines the sets stored at `keys` with `op`, replying WRONG_TYPE if any
+/// of them holds another type.
+fn combine_sets(db: &mut Keyspace, db_instance: &DbInstance, keys: &[String], op: SetOp) -> Result<Set, Reply> {
+    let mut result: Option<Set> = None;
+    for key in keys {
+        let empty = Set::new();
+        let members = set_mut(db, db_instance, key)?.map_or(&empty, |members| &*members);
+        result = Some(match result {
+            None => members.clone(),
+            Some(mut result) => {
+                match op {
+                    SetOp::Union => result.extend(members.iter().cloned()),
+                    SetOp::Intersection => result.retain(|member| members.contains(member)),
+                    SetOp::Difference => result.retain(|member| !members.contains(member)),
+                }
+                result
+            }
+        });
+    }
+    Ok(result.unwrap_or_default())
+}
+
+/// Looks up the set stored at `key`, purging it first if it has expired.
+/// Replies WRONG_TYPE if the key holds another type.
+fn set_mut<'a>(db: &'a mut Keyspace, db_instance: &DbInstance, key: &str) -> Result<Option<&'a mut Set>, Reply> {
+    purge_if_expired(db, db_instance, key);
+    match db.get_mut(key).map(|entry| &mut entry.value) {
+        Some(Value::Set(members)) => Ok(Some(members)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Runs a read-only command on the set stored at `key` (`None` if the key
+/// doesn't exist).
+fn with_set(key: &str, current_db_instance: &Option<Arc<DbInstance>>, read: impl FnOnce(Option<&Set>) -> Reply) -> Reply {
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match set_mut(&mut db, db_instance, key) {
+                Ok(members) => read(members.map(|members| &*members)),
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::testing::TestDb;
+
+    #[test]
+    fn members_are_added_and_removed() {
+        let db = TestDb::new("set_test_members");
+        assert_eq!(db.run("SADD(\"s\",\"b\",\"a\",\"b\")"), "2");
+        assert_eq!(db.run("SADD(\"s\",\"a\",\"c\")"), "1");
+        assert_eq!(db.run("SADD(\"s\",\"a\")"), "0");
+        assert_eq!(db.run("SMEMBERS(\"s\")"), "a\nb\nc");
+        assert_eq!(db.run("SISMEMBER(\"s\",\"b\")"), "1");
+        assert_eq!(db.run("SISMEMBER(\"s\",\"x\")"), "0");
+        assert_eq!(db.run("SCARD(\"s\")"), "3");
+        assert_eq!(db.run("SREM(\"s\",\"a\",\"a\",\"x\")"), "1");
+        assert_eq!(db.run("SREM(\"s\",\"b\",\"c\")"), "2");
+        assert_eq!(db.run("EXISTS(\"s\")"), "0");
+        assert_eq!(db.run("SCARD(\"s\")"), "0");
+    }
+
+    #[test]
+    fn sets_are_combined_and_stored() {
+        let db = TestDb::new("set_test_combine");
+        db.run("SADD(\"x\",\"a\",\"b\",\"c\")");
+        db.run("SADD(\"y\",\"b\",\"c\",\"d\")");
+        assert_eq!(db.run("SUNION(\"x\",\"y\")"), "a\nb\nc\nd");
+        assert_eq!(db.run("SINTER(\"x\",\"y\")"), "b\nc");
+        assert_eq!(db.run("SDIFF(\"x\",\"y\")"), "a");
+        assert_eq!(db.run("SINTER(\"x\",\"missing\")"), "");
+        assert_eq!(db.run("SUNION(\"x\",\"missing\")"), "a\nb\nc");
+
+        db.run("SET(\"d\",\"v\",\"1h\")");
+        assert_eq!(db.run("SINTERSTORE(\"d\",\"x\",\"y\")"), "2");
+        assert_eq!(db.run("SMEMBERS(\"d\")"), "b\nc");
+        assert_eq!(db.run("TTL(\"d\")"), "-1");
+        assert_eq!(db.run("SDIFFSTORE(\"d\",\"x\",\"x\")"), "0");
+        assert_eq!(db.run("EXISTS(\"d\")"), "0");
+    }
+
+    #[test]
+    fn changes_are_replayed_after_a_restart() {
+        let mut db = TestDb::new("set_test_replay");
+        db.run("SADD(\"s\",\"a\",\"b\",\"c\")");
+        db.run("SREM(\"s\",\"b\")");
+        db.run("SADD(\"s\",\"d\")");
+        db.run("SADD(\"gone\",\"x\")");
+        db.run("SREM(\"gone\",\"x\")");
+        db.run("SUNIONSTORE(\"u\",\"s\",\"missing\")");
+        let version = db.version("s");
+
+        db.reload();
+        assert_eq!(db.run("SMEMBERS(\"s\")"), "a\nc\nd");
+        assert_eq!(db.version("s"), version);
+        assert_eq!(db.run("EXISTS(\"gone\")"), "0");
+        assert_eq!(db.run("SMEMBERS(\"u\")"), "a\nc\nd");
+    }
+}
@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::config::SnapshotFormat;
+use crate::db::{JsonBytes, SerializableDb, SerializableValue, SerializableValueWithExpiry, SNAPSHOT_VERSION};
+use crate::index::IndexDefinition;
+
+// =======================================================
+// 🧠 INFO: Binary snapshot layout (all integers little-endian)
+// =======================================================
+// magic        8 bytes  "DBSNAP\0\0"
+// version      u32      layout version (BINARY_VERSION)
+// require_auth u8       0 or 1
+// username     opt-str  u8 presence flag, then str if present
+// password     opt-str
+// revision     u64      highest entry version (since version 2)
+// index count  u32      then str name and str path per secondary index (since version 4)
+// key count    u64
+// per key:     str key, value, u8 expiry flag, then u64 Unix ms if present,
+//              then u64 version and u64 modification time in Unix ms (since version 2)
+// value:       u8 type tag (since version 3, plain bytes before), then
+//              0 string: bytes
+//              1 list:   u64 length, then bytes per element
+//              2 hash:   u64 length, then str field and bytes value per field
+//              3 set:    u64 length, then bytes per member
+//              4 zset:   u64 length, then bytes member and f64 score per member
+//              5 json:   bytes holding the document as JSON text
+// checksum     u32      CRC-32 (IEEE) of every preceding byte
+//
+// str/bytes are a u32 length followed by that many bytes. Files from
+// version 1 on are still read.
+
+const MAGIC: &[u8; 8] = b"DBSNAP\0\0";
+const BINARY_VERSION: u32 = 4;
+
+/// Encodes a snapshot in the given format.
+pub fn encode(db: &SerializableDb, format: SnapshotFormat) -> io::Result<Vec<u8>> {
+    match format {
+        SnapshotFormat::Json => serde_json::to_vec_pretty(db).map_err(io::Error::other),
+        SnapshotFormat::Binary => Ok(encode_binary(db)),
+    }
+}
+
+/// Decodes a snapshot written in the given format.
+pub fn decode(bytes: &[u8], format: SnapshotFormat) -> io::Result<SerializableDb> {
+    match format {
+        SnapshotFormat::Json => serde_json::from_slice(bytes).map_err(io::Error::other),
+        SnapshotFormat::Binary => decode_binary(bytes),
+    }
+}
+
+fn encode_binary(db: &SerializableDb) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+    out.push(db.require_auth as u8);
+    write_opt_str(&mut out, db.username.as_deref());
+    write_opt_str(&mut out, db.password.as_deref());
+    out.extend_from_slice(&db.revision.to_le_bytes());
+    out.extend_from_slice(&(db.indexes.len() as u32).to_le_bytes());
+    for index in &db.indexes {
+        write_bytes(&mut out, index.name.as_bytes());
+        write_bytes(&mut out, index.path.as_bytes());
+    }
+
+    out.extend_from_slice(&(db.data.len() as u64).to_le_bytes());
+    for (key, val) in &db.data {
+        write_bytes(&mut out, key.as_bytes());
+        write_value(&mut out, &val.value);
+        match val.expires_at {
+            Some(ms) => {
+                out.push(1);
+                out.extend_from_slice(&ms.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+        out.extend_from_slice(&val.version.to_le_bytes());
+        out.extend_from_slice(&val.modified_at.to_le_bytes());
+    }
+
+    let checksum = crc32(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+fn decode_binary(bytes: &[u8]) -> io::Result<SerializableDb> {
+    if bytes.len() < MAGIC.len() + 4 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a binary snapshot"));
+    }
+
+    // Verify the trailing checksum before trusting any length field
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+    if crc32(body) != expected {
+        return Err(invalid("snapshot checksum mismatch"));
+    }
+
+    let mut reader = Reader { buf: body, pos: MAGIC.len() };
+    let version = reader.u32()?;
+    if !(1..=BINARY_VERSION).contains(&version) {
+        return Err(invalid(&format!("unsupported binary snapshot version {}", version)));
+    }
+
+    let require_auth = reader.u8()? != 0;
+    let username = reader.opt_string()?;
+    let password = reader.opt_string()?;
+    let revision = if version >= 2 { reader.u64()? } else { 0 };
+    let mut indexes = Vec::new();
+    if version >= 4 {
+        for _ in 0..reader.u32()? {
+            indexes.push(IndexDefinition { name: reader.string()?, path: reader.string()? });
+        }
+    }
+
+    let count = reader.u64()?;
+    let mut data = HashMap::new();
+    for _ in 0..count {
+        let key = reader.string()?;
+        let value = if version >= 3 { reader.value()? } else { SerializableValue::String(reader.bytes()?) };
+        let expires_at = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.u64()?),
+        };
+        let (entry_version, modified_at) = if version >= 2 {
+            (reader.u64()?, reader.u64()?)
+        } else {
+            (0, 0)
+        };
+        data.insert(
+            key,
+            SerializableValueWithExpiry {
+                value,
+                expires_at,
+                version: entry_version,
+                modified_at,
+            },
+        );
+    }
+
+    if reader.pos != body.len() {
+        return Err(invalid("trailing bytes in snapshot"));
+    }
+
+    Ok(SerializableDb {
+        // Older files hold the same data as JSON snapshots of that version
+        version: if version >= 4 { SNAPSHOT_VERSION } else { version },
+        revision,
+        data,
+        indexes,
+        require_auth,
+        username,
+        password,
+    })
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn write_value(out: &mut Vec<u8>, value: &SerializableValue) {
+    match value {
+        SerializableValue::String(bytes) => {
+            out.push(0);
+            write_bytes(out, bytes);
+        }
+        SerializableValue::List(items) => {
+            out.push(1);
+            out.extend_from_slice(&(items.len() as u64).to_le_bytes());
+            for item in items {
+                write_bytes(out, &item.0);
+            }
+        }
+        SerializableValue::Hash(fields) => {
+            out.push(2);
+            out.extend_from_slice(&(fields.len() as u64).to_le_bytes());
+            for (field, value) in fields {
+                write_bytes(out, field.as_bytes());
+                write_bytes(out, &value.0);
+            }
+        }
+        SerializableValue::Set(members) => {
+            out.push(3);
+            out.extend_from_slice(&(members.len() as u64).to_le_bytes());
+            for member in members {
+                write_bytes(out, &member.0);
+            }
+        }
+        SerializableValue::Zset(members) => {
+            out.push(4);
+            out.extend_from_slice(&(members.len() as u64).to_le_bytes());
+            for (member, score) in members {
+                write_bytes(out, &member.0);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        SerializableValue::Json(doc) => {
+            out.push(5);
+            write_bytes(out, doc.to_string().as_bytes());
+        }
+    }
+}
+
+fn write_opt_str(out: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            out.push(1);
+            write_bytes(out, s.as_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Bounds-checked cursor over a snapshot body.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> io::Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| invalid("truncated snapshot"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        let score = f64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        if !score.is_finite() {
+            return Err(invalid("invalid score in snapshot"));
+        }
+        Ok(score)
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid("invalid UTF-8 in snapshot"))
+    }
+
+    fn value(&mut self) -> io::Result<SerializableValue> {
+        match self.u8()? {
+            0 => Ok(SerializableValue::String(self.bytes()?)),
+            1 => {
+                let len = self.length(4)?;
+                let items = (0..len).map(|_| self.bytes().map(JsonBytes)).collect::<io::Result<_>>()?;
+                Ok(SerializableValue::List(items))
+            }
+            2 => {
+                let len = self.length(8)?;
+                let fields = (0..len)
+                    .map(|_| Ok((self.string()?, JsonBytes(self.bytes()?))))
+                    .collect::<io::Result<_>>()?;
+                Ok(SerializableValue::Hash(fields))
+            }
+            3 => {
+                let len = self.length(4)?;
+                let members = (0..len).map(|_| self.bytes().map(JsonBytes)).collect::<io::Result<_>>()?;
+                Ok(SerializableValue::Set(members))
+            }
+            4 => {
+                let len = self.length(12)?;
+                let members = (0..len)
+                    .map(|_| Ok((JsonBytes(self.bytes()?), self.f64()?)))
+                    .collect::<io::Result<_>>()?;
+                Ok(SerializableValue::Zset(members))
+            }
+            5 => {
+                let doc = serde_json::from_slice(&self.bytes()?).map_err(|_| invalid("invalid JSON document in snapshot"))?;
+                Ok(SerializableValue::Json(doc))
+            }
+            tag => Err(invalid(&format!("unknown value type {}", tag))),
+        }
+    }
+
+    /// Reads an element count, rejecting counts that can't fit in the rest
+    /// of the buffer given the minimum size of an element.
+    fn length(&mut self, min_element_size: u64) -> io::Result<u64> {
+        let len = self.u64()?;
+        if len > (self.buf.len() - self.pos) as u64 / min_element_size {
+            return Err(invalid("truncated snapshot"));
+        }
+        Ok(len)
+    }
+
+    fn opt_string(&mut self) -> io::Result<Option<String>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.string()?)),
+        }
+    }
+}
+
+/// Lookup table for CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320).
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC-32 checksum of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn entry(value: SerializableValue, expires_at: Option<u64>) -> SerializableValueWithExpiry {
+        SerializableValueWithExpiry { value, expires_at, version: 7, modified_at: 1_700_000_000_000 }
+    }
+
+    fn sample_db() -> SerializableDb {
+        let bytes = |s: &str| JsonBytes(s.as_bytes().to_vec());
+        let mut data = HashMap::new();
+        data.insert("string".to_string(), entry(SerializableValue::String(vec![0, 159, 146, 150]), Some(42)));
+        data.insert("list".to_string(), entry(SerializableValue::List(vec![bytes("a"), bytes("")]), None));
+        data.insert(
+            "hash".to_string(),
+            entry(SerializableValue::Hash(BTreeMap::from([("field".to_string(), bytes("value"))])), None),
+        );
+        data.insert("set".to_string(), entry(SerializableValue::Set(vec![bytes("x"), bytes("y")]), None));
+        data.insert("zset".to_string(), entry(SerializableValue::Zset(vec![(bytes("m"), -1.5)]), None));
+        data.insert(
+            "json".to_string(),
+            entry(SerializableValue::Json(serde_json::json!({"name": "ada", "tags": [1, 2.5, null]})), None),
+        );
+
+        SerializableDb {
+            version: SNAPSHOT_VERSION,
+            revision: 99,
+            data,
+            indexes: vec![IndexDefinition { name: "by_name".to_string(), path: "$.name".to_string() }],
+            require_auth: true,
+            username: Some("admin".to_string()),
+            password: None,
+        }
+    }
+
+    /// Appends the checksum to a hand-built snapshot body.
+    fn seal(mut body: Vec<u8>) -> Vec<u8> {
+        let checksum = crc32(&body);
+        body.extend_from_slice(&checksum.to_le_bytes());
+        body
+    }
+
+    /// A version 4 header with no auth, no indexes and the given key count.
+    fn header(key_count: u64) -> Vec<u8> {
+        let mut body = MAGIC.to_vec();
+        body.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+        body.extend_from_slice(&[0, 0, 0]);
+        body.extend_from_slice(&0u64.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&key_count.to_le_bytes());
+        body
+    }
+
+    fn decode_error(bytes: &[u8]) -> String {
+        decode(bytes, SnapshotFormat::Binary).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let db = sample_db();
+        let bytes = encode(&db, SnapshotFormat::Binary).unwrap();
+        assert_eq!(decode(&bytes, SnapshotFormat::Binary).unwrap(), db);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let db = sample_db();
+        let bytes = encode(&db, SnapshotFormat::Json).unwrap();
+        assert_eq!(decode(&bytes, SnapshotFormat::Json).unwrap(), db);
+    }
+
+    #[test]
+    fn corrupted_byte_fails_checksum() {
+        let mut bytes = encode(&sample_db(), SnapshotFormat::Binary).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x01;
+        assert_eq!(decode_error(&bytes), "snapshot checksum mismatch");
+
+        let mut bytes = encode(&sample_db(), SnapshotFormat::Binary).unwrap();
+        *bytes.last_mut().unwrap() ^= 0x80;
+        assert_eq!(decode_error(&bytes), "snapshot checksum mismatch");
+    }
+
+    #[test]
+    fn rejects_foreign_and_short_files() {
+        assert_eq!(decode_error(b""), "not a binary snapshot");
+        assert_eq!(decode_error(b"{\"data\":{}}"), "not a binary snapshot");
+        assert_eq!(decode_error(MAGIC), "not a binary snapshot");
+
+        let bytes = encode(&sample_db(), SnapshotFormat::Binary).unwrap();
+        assert_eq!(decode_error(&bytes[..bytes.len() - 1]), "snapshot checksum mismatch");
+    }
+
+    #[test]
+    fn truncated_body_is_rejected() {
+        let bytes = encode(&sample_db(), SnapshotFormat::Binary).unwrap();
+        let body = &bytes[..bytes.len() - 4];
+        for len in [MAGIC.len() + 4, MAGIC.len() + 6, body.len() - 1] {
+            assert_eq!(decode_error(&seal(body[..len].to_vec())), "truncated snapshot", "cut at {}", len);
+        }
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        // A key count far beyond the data runs out of bytes instead of allocating
+        assert_eq!(decode_error(&seal(header(u64::MAX))), "truncated snapshot");
+
+        // A string length past the end of the buffer
+        let mut body = header(1);
+        body.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decode_error(&seal(body)), "truncated snapshot");
+
+        // A collection length that can't fit in the remaining bytes
+        let mut body = header(1);
+        write_bytes(&mut body, b"key");
+        body.push(1);
+        body.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(decode_error(&seal(body)), "truncated snapshot");
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let mut body = header(1);
+        write_bytes(&mut body, b"key");
+        body.push(9);
+        assert_eq!(decode_error(&seal(body)), "unknown value type 9");
+
+        let mut body = header(1);
+        write_bytes(&mut body, b"key");
+        body.push(4);
+        body.extend_from_slice(&1u64.to_le_bytes());
+        write_bytes(&mut body, b"member");
+        body.extend_from_slice(&f64::NAN.to_le_bytes());
+        assert_eq!(decode_error(&seal(body)), "invalid score in snapshot");
+
+        let mut body = header(0);
+        body.push(0);
+        assert_eq!(decode_error(&seal(body)), "trailing bytes in snapshot");
+
+        let mut body = header(0);
+        body[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(BINARY_VERSION + 1).to_le_bytes());
+        assert_eq!(decode_error(&seal(body)), format!("unsupported binary snapshot version {}", BINARY_VERSION + 1));
+    }
+
+    #[test]
+    fn decodes_version_1_files() {
+        let mut body = MAGIC.to_vec();
+        body.extend_from_slice(&1u32.to_le_bytes());
+        body.extend_from_slice(&[0, 0, 0]);
+        body.extend_from_slice(&1u64.to_le_bytes());
+        write_bytes(&mut body, b"key");
+        write_bytes(&mut body, b"value");
+        body.push(0);
+
+        let db = decode(&seal(body), SnapshotFormat::Binary).unwrap();
+        assert_eq!(db.version, 1);
+        assert_eq!(db.revision, 0);
+        assert_eq!(
+            db.data["key"],
+            SerializableValueWithExpiry {
+                value: SerializableValue::String(b"value".to_vec()),
+                expires_at: None,
+                version: 0,
+                modified_at: 0,
+            }
+        );
+    }
+}
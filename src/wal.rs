@@ -0,0 +1,635 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::config::{self, FsyncPolicy};
+use crate::db::{DbMap, JsonBytes, Keyspace, SerializableValueWithExpiry, Value, ValueWithExpiry};
+use crate::json::{self, Segment};
+use crate::list::End;
+use crate::logger::log_info;
+
+/// A single mutation recorded in a database's write-ahead log.
+/// Most records carry the resulting state of a key; `Update` records only
+/// carry what changed, so each must be replayed exactly once (see `LogLine`).
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum LogRecord {
+    // Key was written with the given value and expiry.
+    Set {
+        key: String,
+        #[serde(flatten)]
+        entry: SerializableValueWithExpiry,
+    },
+    // The collection at key was changed in place, creating it (without a
+    // TTL) if needed, and now has the given version and modification time.
+    Update {
+        key: String,
+        version: u64,
+        modified_at: u64,
+        change: Change,
+    },
+    // Key was deleted by a client.
+    Del { key: String },
+    // Key was removed because its TTL elapsed.
+    Expire { key: String },
+    // Every key was deleted (FLUSHDB).
+    Clear,
+    // Several records applied atomically; stored on one line so a torn
+    // write drops the whole batch.
+    Batch { records: Vec<LogRecord> },
+}
+
+/// A change to part of a collection, logged instead of its whole new value
+/// so that a write costs the same however large the collection is.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    // Values were pushed onto one end of a list, in this order.
+    ListPush { end: End, values: Vec<JsonBytes> },
+    // Elements were popped from one end of a list.
+    ListPop { end: End, count: usize },
+    // A list was cut down to the elements from `first` to `last` inclusive.
+    ListTrim { first: usize, last: usize },
+    // Fields of a hash were set to these values.
+    HashSet { fields: BTreeMap<String, JsonBytes> },
+    // Fields were deleted from a hash.
+    HashDel { fields: Vec<String> },
+    // Members were added to a set.
+    SetAdd { members: Vec<JsonBytes> },
+    // Members were removed from a set.
+    SetRem { members: Vec<JsonBytes> },
+    // Members of a sorted set were given these scores, in this order.
+    ZsetAdd { members: Vec<(JsonBytes, f64)> },
+    // Members were removed from a sorted set.
+    ZsetRem { members: Vec<JsonBytes> },
+    // The node at `path` of a JSON document was replaced or added.
+    JsonSet { path: Vec<Segment>, value: serde_json::Value },
+    // The node at a non-root `path` of a JSON document was deleted.
+    JsonDel { path: Vec<Segment> },
+    // Values were appended to the array at `path` of a JSON document.
+    JsonAppend { path: Vec<Segment>, values: Vec<serde_json::Value> },
+}
+
+impl Change {
+    /// An empty collection of the type the change applies to.
+    fn empty_value(&self) -> Value {
+        match self {
+            Self::ListPush { .. } | Self::ListPop { .. } | Self::ListTrim { .. } => Value::List(Default::default()),
+            Self::HashSet { .. } | Self::HashDel { .. } => Value::Hash(Default::default()),
+            Self::SetAdd { .. } | Self::SetRem { .. } => Value::Set(Default::default()),
+            Self::ZsetAdd { .. } | Self::ZsetRem { .. } => Value::SortedSet(Default::default()),
+            Self::JsonSet { .. } | Self::JsonDel { .. } | Self::JsonAppend { .. } => Value::Json(Default::default()),
+        }
+    }
+
+    /// Applies the change to `value`, or returns false if it holds another
+    /// type or has no node at the path of a JSON change.
+    fn apply(self, value: &mut Value) -> bool {
+        match (self, value) {
+            (Self::ListPush { end, values }, Value::List(items)) => {
+                for JsonBytes(value) in values {
+                    match end {
+                        End::Left => items.push_front(value),
+                        End::Right => items.push_back(value),
+                    }
+                }
+            }
+            (Self::ListPop { end, count }, Value::List(items)) => {
+                let count = count.min(items.len());
+                match end {
+                    End::Left => drop(items.drain(..count)),
+                    End::Right => items.truncate(items.len() - count),
+                }
+            }
+            (Self::ListTrim { first, last }, Value::List(items)) => {
+                items.truncate(last + 1);
+                items.drain(..first.min(items.len()));
+            }
+            (Self::HashSet { fields }, Value::Hash(hash)) => {
+                hash.extend(fields.into_iter().map(|(field, JsonBytes(value))| (field, value)));
+            }
+            (Self::HashDel { fields }, Value::Hash(hash)) => {
+                for field in fields {
+                    hash.remove(&field);
+                }
+            }
+            (Self::SetAdd { members }, Value::Set(set)) => {
+                set.extend(members.into_iter().map(|JsonBytes(member)| member));
+            }
+            (Self::SetRem { members }, Value::Set(set)) => {
+                for JsonBytes(member) in members {
+                    set.remove(&member);
+                }
+            }
+            (Self::ZsetAdd { members }, Value::SortedSet(set)) => {
+                for (JsonBytes(member), score) in members {
+                    set.insert(member, score);
+                }
+            }
+            (Self::ZsetRem { members }, Value::SortedSet(set)) => {
+                for JsonBytes(member) in members {
+                    set.remove(&member);
+                }
+            }
+            (Self::JsonSet { path, value }, Value::Json(doc)) => return json::set_at(doc, &path, value).is_ok(),
+            (Self::JsonDel { path }, Value::Json(doc)) => return json::delete_at(doc, &path),
+            (Self::JsonAppend { path, values }, Value::Json(doc)) => match json::lookup_mut(doc, &path) {
+                Some(serde_json::Value::Array(items)) => items.extend(values),
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A line of the log: a record and the database revision it was logged at.
+/// Every record gets a revision of its own, so a snapshot taken at revision
+/// R already holds the effect of every record up to R. Logs written before
+/// revisions were recorded read as revision 0 and are always applied.
+#[derive(Serialize, Deserialize)]
+struct LogLine<R> {
+    #[serde(default)]
+    rev: u64,
+    #[serde(flatten)]
+    record: R,
+}
+
+/// Append-only log of mutations applied to a database since its last snapshot.
+#[derive(Debug)]
+pub struct Wal {
+    // Database name, used to locate the log files.
+    name: String,
+    file: File,
+    // Bytes currently in the log.
+    size: u64,
+    // Whether records were appended since the last fsync.
+    needs_sync: bool,
+}
+
+/// Returns the path of the write-ahead log for a database.
+pub fn log_path(name: &str) -> String {
+    format!("dbs/{}.log", name)
+}
+
+/// Returns the path of the log covering the span between the backup
+/// snapshot and the current one.
+pub fn backup_log_path(name: &str) -> String {
+    format!("{}.bak", log_path(name))
+}
+
+impl Wal {
+    /// Opens (or creates) the write-ahead log of a database for appending.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path(name))?;
+        let size = file.metadata()?.len();
+
+        Ok(Self { name: name.to_string(), file, size, needs_sync: false })
+    }
+
+    /// Appends a record logged at revision `rev` to the log, syncing it
+    /// according to the fsync policy.
+    pub fn append(&mut self, rev: u64, record: &LogRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&LogLine { rev, record }).map_err(io::Error::other)?;
+        line.push(b'\n');
+
+        self.file.write_all(&line)?;
+        self.size += line.len() as u64;
+
+        match config::get().appendfsync {
+            FsyncPolicy::Always => self.file.sync_data()?,
+            FsyncPolicy::EverySec => self.needs_sync = true,
+            FsyncPolicy::Never => {}
+        }
+        Ok(())
+    }
+
+    /// Flushes appended records to stable storage if any are pending.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.needs_sync {
+            self.file.sync_data()?;
+            self.needs_sync = false;
+        }
+        Ok(())
+    }
+
+    /// Starts a fresh log once the first `covered` bytes of records are held
+    /// by a new snapshot; records appended since it was taken move to the
+    /// fresh log. The old log is kept whole alongside the backup snapshot so
+    /// that backup plus backup log still reproduce the current snapshot.
+    pub fn rotate(&mut self, covered: u64) -> io::Result<()> {
+        self.file.sync_all()?;
+        let path = log_path(&self.name);
+        let backup = backup_log_path(&self.name);
+        let tmp_path = format!("{}.tmp", path);
+
+        let mut old = File::open(&path)?;
+        old.seek(SeekFrom::Start(covered))?;
+        let mut fresh = File::create(&tmp_path)?;
+        io::copy(&mut old, &mut fresh)?;
+        fresh.sync_all()?;
+        drop(fresh);
+
+        // Swap in the fresh log without ever removing the live one: until the
+        // rename, the old log still replays correctly on top of either snapshot
+        match fs::remove_file(&backup) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        if fs::hard_link(&path, &backup).is_err() {
+            fs::copy(&path, &backup)?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        File::open("dbs")?.sync_all()?;
+
+        *self = Self::open(&self.name)?;
+        Ok(())
+    }
+
+    /// Current size of the log in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Replays the write-ahead log of a database on top of `data`, which holds
+/// every mutation up to `revision`. Records at or below it are skipped, and
+/// `revision` is raised to the last record applied.
+/// Returns the number of records applied. A torn trailing record (an
+/// unterminated last line, e.g. from a crash mid-append) was never
+/// acknowledged: it is cut off the log so that later appends start on a
+/// fresh line. Any other unreadable record fails the load and leaves the log
+/// untouched, since the records after it can't be applied without it.
+pub fn replay(name: &str, data: &mut Keyspace, revision: &mut u64) -> io::Result<usize> {
+    replay_file(name, &log_path(name), data, revision)
+}
+
+/// Replays the backup log on top of `data`, which was loaded from the
+/// backup snapshot.
+pub fn replay_backup(name: &str, data: &mut Keyspace, revision: &mut u64) -> io::Result<usize> {
+    replay_file(name, &backup_log_path(name), data, revision)
+}
+
+fn replay_file(name: &str, path: &str, data: &mut Keyspace, revision: &mut u64) -> io::Result<usize> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let file_len = file.metadata()?.len();
+
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut applied = 0;
+    let mut records = 0;
+    // Offset just past the last record that was read back in full.
+    let mut valid_len = 0;
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        // Records are acknowledged only once their newline is written, and
+        // only the last line can lack one
+        if line.last() != Some(&b'\n') {
+            log_info(&format!("⚠️ Stopped replaying log of '{}' at record {}: incomplete record", name, records + 1));
+            break;
+        }
+
+        if !line.trim_ascii().is_empty() {
+            records += 1;
+            let line: LogLine<LogRecord> = serde_json::from_slice(&line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("corrupt record {} at byte {} of {}: {}", records, valid_len, path, e),
+                )
+            })?;
+
+            if line.rev == 0 || line.rev > *revision {
+                apply(line.record, data);
+                applied += 1;
+                *revision = (*revision).max(line.rev);
+            }
+        }
+        valid_len += read as u64;
+    }
+
+    if valid_len < file_len {
+        truncate(path, valid_len)?;
+        log_info(&format!(
+            "⚠️ Truncated log of '{}' to {} bytes, dropping {} unreadable bytes",
+            name,
+            valid_len,
+            file_len - valid_len
+        ));
+    }
+    Ok(applied)
+}
+
+/// Cuts the log at `path` down to `len` bytes and syncs it, so that records
+/// appended afterwards are not merged into a torn one.
+fn truncate(path: &str, len: u64) -> io::Result<()> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(len)?;
+    file.sync_all()
+}
+
+fn apply(record: LogRecord, data: &mut Keyspace) {
+    match record {
+        LogRecord::Set { key, entry } => {
+            data.insert(key, ValueWithExpiry::from_serializable(entry));
+        }
+        LogRecord::Update { key, version, modified_at, change } => {
+            let created = !data.contains_key(&key);
+            let entry = data.entry(key.clone()).or_insert_with(|| ValueWithExpiry {
+                value: change.empty_value(),
+                expires_at: None,
+                version,
+                modified_at,
+            });
+            if change.apply(&mut entry.value) {
+                entry.version = version;
+                entry.modified_at = modified_at;
+                if entry.value.is_empty_collection() {
+                    data.remove(&key);
+                }
+            } else {
+                let type_name = entry.value.type_name();
+                log_info(&format!("⚠️ Skipped a logged change that doesn't apply to the {} at '{}'", type_name, key));
+                if created {
+                    data.remove(&key);
+                }
+            }
+        }
+        LogRecord::Del { key } | LogRecord::Expire { key } => {
+            data.remove(&key);
+        }
+        LogRecord::Clear => data.clear(),
+        LogRecord::Batch { records } => {
+            for record in records {
+                apply(record, data);
+            }
+        }
+    }
+}
+
+/// Starts a background async task that fsyncs every database's write-ahead
+/// log once per second when the `everysec` policy is in effect.
+pub async fn start_syncer(db_map: DbMap) {
+    if config::get().appendfsync != FsyncPolicy::EverySec {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            {
+                let db_map_lock = db_map.lock().unwrap();
+                for (db_name, db_instance) in db_map_lock.iter() {
+                    if let Err(e) = db_instance.wal.lock().unwrap().sync() {
+                        log_info(&format!("⚠️ Failed to sync log of '{}': {}", db_name, e));
+                    }
+                }
+            }
+
+            sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Value;
+
+    fn set(key: &str, value: &str) -> LogRecord {
+        let entry = ValueWithExpiry::new(Value::String(value.as_bytes().to_vec()), None);
+        LogRecord::Set { key: key.to_string(), entry: entry.to_serializable() }
+    }
+
+    /// Opens an empty log for a database only used by one test.
+    fn fresh_wal(name: &str) -> Wal {
+        fs::create_dir_all("dbs").unwrap();
+        let _ = fs::remove_file(log_path(name));
+        let _ = fs::remove_file(backup_log_path(name));
+        Wal::open(name).unwrap()
+    }
+
+    fn remove_logs(name: &str) {
+        let _ = fs::remove_file(log_path(name));
+        let _ = fs::remove_file(backup_log_path(name));
+    }
+
+    fn append_raw(name: &str, bytes: &[u8]) {
+        OpenOptions::new().append(true).open(log_path(name)).unwrap().write_all(bytes).unwrap();
+    }
+
+    /// Replays the log of `name` onto an empty keyspace.
+    fn load(name: &str) -> io::Result<(Keyspace, usize)> {
+        let mut data = Keyspace::new();
+        let applied = replay(name, &mut data, &mut 0)?;
+        Ok((data, applied))
+    }
+
+    fn value(data: &Keyspace, key: &str) -> Option<Vec<u8>> {
+        match data.get(key).map(|entry| &entry.value) {
+            Some(Value::String(value)) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn replay_applies_records_in_order() {
+        let name = "wal_test_replay_order";
+        let mut wal = fresh_wal(name);
+        wal.append(1, &set("a", "1")).unwrap();
+        wal.append(2, &set("b", "2")).unwrap();
+        wal.append(3, &LogRecord::Batch { records: vec![set("a", "3"), LogRecord::Del { key: "b".to_string() }] })
+            .unwrap();
+
+        let (data, applied) = load(name).unwrap();
+        assert_eq!(applied, 3);
+        assert_eq!(value(&data, "a"), Some(b"3".to_vec()));
+        assert!(!data.contains_key("b"));
+
+        wal.append(4, &LogRecord::Clear).unwrap();
+        let (data, applied) = load(name).unwrap();
+        assert_eq!(applied, 4);
+        assert!(data.is_empty());
+        remove_logs(name);
+    }
+
+    #[test]
+    fn updates_change_collections_once() {
+        let name = "wal_test_updates";
+        let mut wal = fresh_wal(name);
+        let update = |key: &str, version, change| LogRecord::Update { key: key.to_string(), version, modified_at: 0, change };
+        let push = |values: &[&str]| Change::ListPush {
+            end: End::Right,
+            values: values.iter().map(|value| JsonBytes(value.as_bytes().to_vec())).collect(),
+        };
+        wal.append(1, &update("l", 1, push(&["a", "b", "c"]))).unwrap();
+        wal.append(2, &update("l", 2, Change::ListPop { end: End::Left, count: 1 })).unwrap();
+        wal.append(3, &set("s", "1")).unwrap();
+        wal.append(4, &update("l", 4, push(&["d"]))).unwrap();
+
+        let (data, applied) = load(name).unwrap();
+        assert_eq!(applied, 4);
+        let entry = &data["l"];
+        assert_eq!(entry.version, 4);
+        let Value::List(items) = &entry.value else { panic!("expected a list") };
+        assert_eq!(items.iter().map(|item| item.as_slice()).collect::<Vec<_>>(), [b"b", b"c", b"d"]);
+
+        // A change to a key of another type is skipped
+        wal.append(5, &update("s", 5, push(&["x"]))).unwrap();
+        let (data, _) = load(name).unwrap();
+        assert_eq!(value(&data, "s"), Some(b"1".to_vec()));
+
+        // Emptying a collection deletes its key
+        wal.append(6, &update("l", 6, Change::ListTrim { first: 3, last: 3 })).unwrap();
+        let (data, _) = load(name).unwrap();
+        assert!(!data.contains_key("l"));
+
+        // A change that doesn't apply leaves no key behind
+        let path = vec![Segment::Key("a".to_string())];
+        wal.append(7, &update("j", 7, Change::JsonDel { path })).unwrap();
+        let (data, applied) = load(name).unwrap();
+        assert_eq!(applied, 7);
+        assert!(!data.contains_key("j"));
+        remove_logs(name);
+    }
+
+    #[test]
+    fn missing_log_replays_nothing() {
+        let (data, applied) = load("wal_test_missing").unwrap();
+        assert_eq!(applied, 0);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn records_held_by_the_snapshot_are_skipped() {
+        let name = "wal_test_skip";
+        let mut wal = fresh_wal(name);
+        wal.append(5, &set("a", "1")).unwrap();
+        wal.append(6, &set("b", "2")).unwrap();
+        wal.append(7, &LogRecord::Del { key: "a".to_string() }).unwrap();
+
+        // A snapshot at revision 6 already holds a and b
+        let mut data = Keyspace::new();
+        let mut revision = 6;
+        assert_eq!(replay(name, &mut data, &mut revision).unwrap(), 1);
+        assert_eq!(revision, 7);
+
+        // Logs from before revisions were recorded are always applied
+        append_raw(name, b"{\"op\":\"del\",\"key\":\"b\"}\n");
+        let mut revision = 7;
+        assert_eq!(replay(name, &mut data, &mut revision).unwrap(), 1);
+        assert_eq!(revision, 7);
+        remove_logs(name);
+    }
+
+    #[test]
+    fn rotation_keeps_records_after_the_snapshot() {
+        let name = "wal_test_rotate";
+        let mut wal = fresh_wal(name);
+        wal.append(1, &set("a", "1")).unwrap();
+        let covered = wal.size();
+        wal.append(2, &set("b", "2")).unwrap();
+        let total = wal.size();
+        wal.rotate(covered).unwrap();
+
+        assert_eq!(wal.size(), total - covered);
+        let (data, applied) = load(name).unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(value(&data, "b"), Some(b"2".to_vec()));
+
+        // The backup log keeps every record, and replaying both skips the copy
+        let mut data = Keyspace::new();
+        let mut revision = 0;
+        assert_eq!(replay_backup(name, &mut data, &mut revision).unwrap(), 2);
+        assert_eq!(replay(name, &mut data, &mut revision).unwrap(), 0);
+        assert_eq!(data.len(), 2);
+        remove_logs(name);
+    }
+
+    #[test]
+    fn torn_tail_is_truncated_before_appending() {
+        let name = "wal_test_torn_tail";
+        let mut wal = fresh_wal(name);
+        wal.append(1, &set("a", "1")).unwrap();
+        let good_len = wal.size();
+        drop(wal);
+        append_raw(name, br#"{"op":"set","key":"b","ty"#);
+
+        assert_eq!(load(name).unwrap().1, 1);
+        assert_eq!(fs::metadata(log_path(name)).unwrap().len(), good_len);
+
+        let mut wal = Wal::open(name).unwrap();
+        wal.append(2, &set("c", "3")).unwrap();
+        drop(wal);
+
+        let (data, applied) = load(name).unwrap();
+        assert_eq!(applied, 2);
+        assert_eq!(value(&data, "a"), Some(b"1".to_vec()));
+        assert_eq!(value(&data, "c"), Some(b"3".to_vec()));
+        assert!(!data.contains_key("b"));
+        remove_logs(name);
+    }
+
+    #[test]
+    fn torn_first_record_does_not_swallow_later_writes() {
+        let name = "wal_test_torn_first";
+        drop(fresh_wal(name));
+        append_raw(name, br#"{"op":"del","k"#);
+
+        assert_eq!(load(name).unwrap().1, 0);
+        assert_eq!(fs::metadata(log_path(name)).unwrap().len(), 0);
+
+        let mut wal = Wal::open(name).unwrap();
+        wal.append(1, &set("a", "1")).unwrap();
+        drop(wal);
+
+        let (data, applied) = load(name).unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(value(&data, "a"), Some(b"1".to_vec()));
+        remove_logs(name);
+    }
+
+    #[test]
+    fn corrupt_record_fails_replay_and_keeps_the_log() {
+        let name = "wal_test_corrupt";
+        let mut wal = fresh_wal(name);
+        wal.append(1, &set("a", "1")).unwrap();
+        drop(wal);
+        append_raw(name, b"not json\n");
+        let mut wal = Wal::open(name).unwrap();
+        wal.append(2, &set("b", "2")).unwrap();
+        let len = wal.size();
+        drop(wal);
+
+        let error = load(name).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("corrupt record 2 "), "{}", error);
+        // The valid record after the corrupt one is still on disk
+        assert_eq!(fs::metadata(log_path(name)).unwrap().len(), len);
+        remove_logs(name);
+    }
+
+    #[test]
+    fn corrupt_last_line_is_not_mistaken_for_a_torn_one() {
+        let name = "wal_test_corrupt_last";
+        let mut wal = fresh_wal(name);
+        wal.append(1, &set("a", "1")).unwrap();
+        drop(wal);
+        append_raw(name, b"{\"op\":\"bogus\"}\n");
+
+        assert!(load(name).is_err());
+        remove_logs(name);
+    }
+}
@@ -0,0 +1,485 @@
+// =======================================================
+// 🧠 INFO: Sorted Set Commands
+// =======================================================
+// Sorted sets are stored as `Value::SortedSet`: distinct members, each with a
+// finite score, ordered by score and then by member. Ranks start at 0 for the
+// lowest score and may be negative to count from the end, like list indexes.
+// Like lists they are never left empty, and mutations log only the members
+// whose score they set or that they removed.
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::db::{DbInstance, JsonBytes, Keyspace, Value, ValueWithExpiry};
+use crate::list::{index_args, index_range};
+use crate::parser::{key_arg, log_change, no_database, parse_float, parse_integer, purge_if_expired, usage, wrong_type};
+use crate::reply::{ErrorCode, Reply};
+use crate::wal::Change;
+
+/// A score with a total order, so it can key a `BTreeSet`. Never NaN.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(f64);
+
+impl Score {
+    pub fn new(score: f64) -> Self {
+        // Adding 0.0 turns -0.0 into 0.0, which `total_cmp` would order apart
+        Self(score + 0.0)
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members with scores, indexed both by member (for ZSCORE and updates) and
+/// by score order (for ranges), so each member is stored twice.
+///
+/// `BTreeSet` doesn't track subtree sizes, so finding a rank (ZRANK, and the
+/// start of ZRANGE/ZREVRANGE) walks every member before it: O(n) rather than
+/// the O(log n) of an order-statistic tree. Lookups by member, updates and
+/// ranges by score are O(log n) plus the members returned.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, Score>,
+    ordered: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).map(|score| score.0)
+    }
+
+    /// Sets the score of `member`, returning whether it is new.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        let score = Score::new(score);
+        let old = self.scores.insert(member.clone(), score);
+        if let Some(old) = old {
+            self.ordered.remove(&(old, member.clone()));
+        }
+        self.ordered.insert((score, member));
+        old.is_none()
+    }
+
+    /// Removes `member`, returning whether it was present.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.ordered.remove(&(score, member.to_vec())),
+            None => false,
+        }
+    }
+
+    /// Position of `member` in score order, counted in O(rank).
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = *self.scores.get(member)?;
+        Some(self.ordered.range(..(score, member.to_vec())).count())
+    }
+
+    /// Members and scores in score order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.ordered.iter().map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Members and scores between `min` and `max`, in score order.
+    fn range_by_score(&self, min: Bound<f64>, max: Bound<f64>) -> impl Iterator<Item = (&[u8], f64)> {
+        let start = match min {
+            Bound::Included(min) | Bound::Excluded(min) => Bound::Included((Score::new(min), Vec::new())),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.ordered
+            .range((start, Bound::Unbounded))
+            .map(|(score, member)| (member.as_slice(), score.0))
+            .skip_while(move |&(_, score)| matches!(min, Bound::Excluded(min) if score <= min))
+            .take_while(move |&(_, score)| match max {
+                Bound::Included(max) => score <= max,
+                Bound::Excluded(max) => score < max,
+                Bound::Unbounded => true,
+            })
+    }
+}
+
+impl FromIterator<(Vec<u8>, f64)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, f64)>>(iter: I) -> Self {
+        let mut set = Self::default();
+        for (member, score) in iter {
+            set.insert(member, score);
+        }
+        set
+    }
+}
+
+/// ZADD("key","score","member",...) - creates the sorted set if needed, sets
+/// the score of each member and replies with the number of members added
+/// (not just updated); the TTL of an existing set is kept
+pub fn add(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return usage("ZADD(\"key\",\"score\",\"member\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let mut pairs = Vec::new();
+    while let (Some(score), Some(member)) = (args.next(), args.next()) {
+        match parse_float(&score) {
+            Some(score) => pairs.push((member, score)),
+            None => return Reply::error(ErrorCode::InvalidArgument, "Error: Score must be a number"),
+        }
+    }
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let entry = db
+                .entry(key.clone())
+                .or_insert_with(|| ValueWithExpiry::new(Value::SortedSet(SortedSet::default()), None));
+            let Value::SortedSet(set) = &mut entry.value else {
+                return wrong_type();
+            };
+            let mut added = 0;
+            for (member, score) in &pairs {
+                if set.insert(member.clone(), *score) {
+                    added += 1;
+                }
+            }
+            let members = pairs.into_iter().map(|(member, score)| (JsonBytes(member), score)).collect();
+            db_instance.log_change(&key, entry, Change::ZsetAdd { members });
+            Reply::Int(added)
+        }
+        None => no_database(),
+    }
+}
+
+/// ZREM("key","member",...) - replies with the number of members removed,
+/// deleting the key if none are left
+pub fn remove(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() < 2 {
+        return usage("ZREM(\"key\",\"member\",...)");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            let set = match sorted_set_mut(&mut db, db_instance, &key) {
+                Ok(Some(set)) => set,
+                Ok(None) => return Reply::Int(0),
+                Err(e) => return e,
+            };
+
+            let removed: Vec<_> = args.filter(|member| set.remove(member)).map(JsonBytes).collect();
+            let count = removed.len();
+            if count > 0 {
+                log_change(&mut db, db_instance, &key, Change::ZsetRem { members: removed });
+            }
+            Reply::Int(count as i64)
+        }
+        None => no_database(),
+    }
+}
+
+/// ZSCORE("key","member") - replies with the score of a member, or nil if
+/// the member or key doesn't exist
+pub fn score(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("ZSCORE(\"key\",\"member\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let member = args.next().unwrap();
+
+    with_sorted_set(&key, current_db_instance, |set| {
+        set.and_then(|set| set.score(&member)).map_or(Reply::Nil, score_reply)
+    })
+}
+
+/// ZINCRBY("key","increment","member") - adds to the score of a member (a
+/// missing member counts as 0) and replies with the new score
+pub fn incr_by(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 3 {
+        return usage("ZINCRBY(\"key\",\"increment\",\"member\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let Some(delta) = parse_float(&args.next().unwrap()) else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Increment must be a number");
+    };
+    let member = args.next().unwrap();
+
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            purge_if_expired(&mut db, db_instance, &key);
+            let entry = db
+                .entry(key.clone())
+                .or_insert_with(|| ValueWithExpiry::new(Value::SortedSet(SortedSet::default()), None));
+            let Value::SortedSet(set) = &mut entry.value else {
+                return wrong_type();
+            };
+            let new = set.score(&member).unwrap_or(0.0) + delta;
+            if !new.is_finite() {
+                // Don't leave a newly created set behind empty
+                if set.is_empty() {
+                    db.remove(&key);
+                }
+                return Reply::error(ErrorCode::InvalidArgument, "Error: Increment would overflow");
+            }
+            set.insert(member.clone(), new);
+            db_instance.log_change(&key, entry, Change::ZsetAdd { members: vec![(JsonBytes(member), new)] });
+            score_reply(new)
+        }
+        None => no_database(),
+    }
+}
+
+/// ZRANGE/ZREVRANGE("key","start","stop",[WITHSCORES]) - replies with the
+/// members from rank `start` to `stop` inclusive, from the lowest score
+/// (ZRANGE) or the highest (ZREVRANGE). Skipping to `start` is O(start)
+pub fn range(args: Vec<Vec<u8>>, reverse: bool, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    let usage_text = if reverse {
+        "ZREVRANGE(\"key\",\"start\",\"stop\",[WITHSCORES])"
+    } else {
+        "ZRANGE(\"key\",\"start\",\"stop\",[WITHSCORES])"
+    };
+    if args.len() != 3 && args.len() != 4 {
+        return usage(usage_text);
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let (start, stop) = match index_args(args.by_ref().take(2)) {
+        Ok(indexes) => indexes,
+        Err(e) => return e,
+    };
+    let with_scores = match args.next() {
+        Some(option) if option.eq_ignore_ascii_case(b"WITHSCORES") => true,
+        Some(_) => return usage(usage_text),
+        None => false,
+    };
+
+    with_sorted_set(&key, current_db_instance, |set| {
+        let Some(set) = set else {
+            return Reply::Array(Vec::new());
+        };
+        let Some((first, last)) = index_range(set.len(), start, stop) else {
+            return Reply::Array(Vec::new());
+        };
+        let members: Box<dyn Iterator<Item = (&[u8], f64)>> =
+            if reverse { Box::new(set.iter().rev()) } else { Box::new(set.iter()) };
+        members_reply(members.skip(first).take(last - first + 1), with_scores)
+    })
+}
+
+/// ZRANGEBYSCORE("key","min","max",[WITHSCORES],[LIMIT,"offset","count"]) -
+/// replies with the members whose score is between `min` and `max`, lowest
+/// first. Bounds are inclusive unless prefixed with "(", and may be "-inf"
+/// or "+inf"; LIMIT skips `offset` members and returns at most `count`
+pub fn range_by_score(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    const USAGE: &str = "ZRANGEBYSCORE(\"key\",\"min\",\"max\",[WITHSCORES],[LIMIT,\"offset\",\"count\"])";
+    if args.len() < 3 {
+        return usage(USAGE);
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let (Some(min), Some(max)) = (parse_bound(&args.next().unwrap()), parse_bound(&args.next().unwrap())) else {
+        return Reply::error(ErrorCode::InvalidArgument, "Error: Min and max must be numbers, optionally prefixed with '('");
+    };
+
+    let mut with_scores = false;
+    let mut offset = 0;
+    let mut count = None;
+    while let Some(option) = args.next() {
+        if option.eq_ignore_ascii_case(b"WITHSCORES") {
+            with_scores = true;
+        } else if option.eq_ignore_ascii_case(b"LIMIT") {
+            let (Some(limit_offset), Some(limit_count)) = (args.next(), args.next()) else {
+                return usage(USAGE);
+            };
+            let limit_offset = parse_integer(&limit_offset).and_then(|n| usize::try_from(n).ok());
+            let Some(limit_offset) = limit_offset else {
+                return Reply::error(ErrorCode::InvalidArgument, "Error: Offset must be a non-negative integer");
+            };
+            let Some(limit_count) = parse_integer(&limit_count) else {
+                return Reply::error(ErrorCode::InvalidArgument, "Error: Count must be an integer");
+            };
+            offset = limit_offset;
+            // A negative count returns every member after the offset
+            count = usize::try_from(limit_count).ok();
+        } else {
+            return usage(USAGE);
+        }
+    }
+
+    with_sorted_set(&key, current_db_instance, |set| {
+        let Some(set) = set else {
+            return Reply::Array(Vec::new());
+        };
+        let members = set.range_by_score(min, max).skip(offset).take(count.unwrap_or(usize::MAX));
+        members_reply(members, with_scores)
+    })
+}
+
+/// ZRANK("key","member") - replies with the rank of a member (0 for the
+/// lowest score), or nil if the member or key doesn't exist. O(rank)
+pub fn rank(args: Vec<Vec<u8>>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    if args.len() != 2 {
+        return usage("ZRANK(\"key\",\"member\")");
+    }
+    let mut args = args.into_iter();
+    let key = match key_arg(args.next().unwrap()) {
+        Ok(key) => key,
+        Err(e) => return e,
+    };
+    let member = args.next().unwrap();
+
+    with_sorted_set(&key, current_db_instance, |set| {
+        set.and_then(|set| set.rank(&member)).map_or(Reply::Nil, |rank| Reply::Int(rank as i64))
+    })
+}
+
+/// Reply carrying a score, formatted like INCRBYFLOAT results.
+pub fn score_reply(score: f64) -> Reply {
+    Reply::Value(score.to_string().into_bytes())
+}
+
+/// Replies with members, each followed by its score if `with_scores` is set.
+fn members_reply<'a>(members: impl Iterator<Item = (&'a [u8], f64)>, with_scores: bool) -> Reply {
+    let mut items = Vec::new();
+    for (member, score) in members {
+        items.push(Reply::Value(member.to_vec()));
+        if with_scores {
+            items.push(score_reply(score));
+        }
+    }
+    Reply::Array(items)
+}
+
+/// Parses a ZRANGEBYSCORE bound: a number, "-inf"/"+inf", or "(" followed
+/// by a number for an exclusive bound.
+fn parse_bound(arg: &[u8]) -> Option<Bound<f64>> {
+    let (exclusive, score) = match arg {
+        [b'(', score @ ..] => (true, score),
+        score => (false, score),
+    };
+    // Scores are finite, so infinite bounds include everything on their side
+    let score = match score {
+        b"-inf" => f64::NEG_INFINITY,
+        b"+inf" | b"inf" => f64::INFINITY,
+        score => parse_float(score)?,
+    };
+    Some(if exclusive { Bound::Excluded(score) } else { Bound::Included(score) })
+}
+
+/// Looks up the sorted set stored at `key`, purging it first if it has
+/// expired. Replies WRONG_TYPE if the key holds another type.
+fn sorted_set_mut<'a>(
+    db: &'a mut Keyspace,
+    db_instance: &DbInstance,
+    key: &str,
+) -> Result<Option<&'a mut SortedSet>, Reply> {
+    purge_if_expired(db, db_instance, key);
+    match db.get_mut(key).map(|entry| &mut entry.value) {
+        Some(Value::SortedSet(set)) => Ok(Some(set)),
+        Some(_) => Err(wrong_type()),
+        None => Ok(None),
+    }
+}
+
+/// Runs a read-only command on the sorted set stored at `key` (`None` if the
+/// key doesn't exist).
+fn with_sorted_set(
+    key: &str,
+    current_db_instance: &Option<Arc<DbInstance>>,
+    read: impl FnOnce(Option<&SortedSet>) -> Reply,
+) -> Reply {
+    match current_db_instance {
+        Some(db_instance) => {
+            let mut db = db_instance.data.lock().unwrap();
+            match sorted_set_mut(&mut db, db_instance, key) {
+                Ok(set) => read(set.map(|set| &*set)),
+                Err(e) => e,
+            }
+        }
+        None => no_database(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::testing::TestDb;
+
+    #[test]
+    fn ranks_and_ranges_follow_score_order() {
+        let db = TestDb::new("zset_test_ranges");
+        assert_eq!(db.run("ZADD(\"z\",\"3\",\"c\",\"1\",\"a\",\"2\",\"b\",\"2\",\"bb\")"), "4");
+        assert_eq!(db.run("ZADD(\"z\",\"0.5\",\"c\",\"4\",\"d\")"), "1");
+        assert_eq!(db.run("ZRANGE(\"z\",\"0\",\"-1\")"), "c\na\nb\nbb\nd");
+        assert_eq!(db.run("ZRANGE(\"z\",\"-2\",\"-1\",\"WITHSCORES\")"), "bb\n2\nd\n4");
+        assert_eq!(db.run("ZREVRANGE(\"z\",\"0\",\"1\")"), "d\nbb");
+        assert_eq!(db.run("ZRANGE(\"z\",\"3\",\"1\")"), "");
+        assert_eq!(db.run("ZRANK(\"z\",\"c\")"), "0");
+        assert_eq!(db.run("ZRANK(\"z\",\"bb\")"), "3");
+        assert_eq!(db.run("ZRANK(\"z\",\"x\")"), "(nil)");
+        assert_eq!(db.run("ZRANGEBYSCORE(\"z\",\"(1\",\"+inf\")"), "b\nbb\nd");
+        assert_eq!(db.run("ZRANGEBYSCORE(\"z\",\"-inf\",\"2\",\"LIMIT\",\"1\",\"2\")"), "a\nb");
+
+        assert_eq!(db.run("ZINCRBY(\"z\",\"2.5\",\"a\")"), "3.5");
+        assert_eq!(db.run("ZSCORE(\"z\",\"a\")"), "3.5");
+        assert_eq!(db.run("ZRANK(\"z\",\"a\")"), "3");
+        assert_eq!(db.run("ZREM(\"z\",\"a\",\"a\",\"x\")"), "1");
+        assert_eq!(db.run("ZREM(\"z\",\"b\",\"bb\",\"c\",\"d\")"), "4");
+        assert_eq!(db.run("EXISTS(\"z\")"), "0");
+    }
+
+    #[test]
+    fn changes_are_replayed_after_a_restart() {
+        let mut db = TestDb::new("zset_test_replay");
+        db.run("ZADD(\"z\",\"1\",\"a\",\"2\",\"b\",\"3\",\"c\",\"9\",\"a\")");
+        db.run("ZINCRBY(\"z\",\"-8.5\",\"c\")");
+        db.run("ZREM(\"z\",\"b\")");
+        db.run("ZINCRBY(\"z\",\"1\",\"d\")");
+        db.run("ZADD(\"gone\",\"1\",\"x\")");
+        db.run("ZREM(\"gone\",\"x\")");
+        let version = db.version("z");
+
+        db.reload();
+        assert_eq!(db.run("ZRANGE(\"z\",\"0\",\"-1\",\"WITHSCORES\")"), "c\n-5.5\nd\n1\na\n9");
+        assert_eq!(db.version("z"), version);
+        assert_eq!(db.run("EXISTS(\"gone\")"), "0");
+    }
+}